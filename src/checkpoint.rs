@@ -0,0 +1,145 @@
+use std::fs::File;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use rustbreak::backend::Backend;
+use rustbreak::error::BackendResult;
+
+// a file backend that never leaves a half written database behind. each save
+// goes to a temporary file next to the real one and is renamed over it once
+// it is safely on disk, and the copies it replaces are kept as numbered
+// checkpoints in a directory next to it: checkpoints/db.1.ron is the previous
+// save, checkpoints/db.2.ron the one before that. keeping them out of the
+// database's own directory means they can't be mistaken for, or overwrite,
+// another database there such as a policy named db.1
+#[derive(Debug)]
+pub struct CheckpointBackend {
+    path: PathBuf,
+    // how many old copies to keep, 0 for none
+    checkpoints: usize,
+    // bytes to write to the path next to them before the first save
+    // replaces the file, see keep_before_saving
+    original: Option<(PathBuf, Vec<u8>)>,
+}
+
+impl CheckpointBackend {
+    // returns the backend and whether path had to be created
+    pub fn open(path: &Path, checkpoints: usize) -> BackendResult<(CheckpointBackend, bool)> {
+        let created = !path.exists();
+        if created {
+            File::create(path)?;
+        }
+        let backend = CheckpointBackend {
+            path: path.to_path_buf(),
+            checkpoints: checkpoints,
+            original: None,
+        };
+        return Ok((backend, created));
+    }
+
+    // like open, but for databases that are only read from: a missing path
+    // is an error rather than a new empty database
+    pub fn open_existing(path: &Path, checkpoints: usize) -> BackendResult<CheckpointBackend> {
+        if !path.is_file() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} does not exist", path.display()),
+            )
+            .into());
+        }
+        return Ok(CheckpointBackend {
+            path: path.to_path_buf(),
+            checkpoints: checkpoints,
+            original: None,
+        });
+    }
+
+    // has the first save copy bytes, what the file held when it was read, to
+    // backup before overwriting it. until something is saved the file is
+    // left alone
+    pub fn keep_before_saving(&mut self, backup: PathBuf, bytes: Vec<u8>) {
+        self.original = Some((backup, bytes));
+    }
+
+    // where the n-th most recent save before the current one is kept
+    pub fn checkpoint_path(&self, n: usize) -> PathBuf {
+        return checkpoint_path(&self.path, &n.to_string());
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let mut name = self
+            .path
+            .file_name()
+            .map(|name| name.to_os_string())
+            .unwrap_or_default();
+        name.push(".tmp");
+        return self.path.with_file_name(name);
+    }
+
+    // shifts every checkpoint down by one, dropping the oldest, and moves the
+    // current file into the first slot
+    fn rotate(&self) -> std::io::Result<()> {
+        if self.checkpoints == 0 {
+            return Ok(());
+        }
+        match std::fs::metadata(&self.path) {
+            Ok(metadata) if metadata.len() > 0 => {}
+            // nothing worth keeping yet
+            _ => return Ok(()),
+        }
+        if let Some(dir) = self.checkpoint_path(1).parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        for n in (1..self.checkpoints).rev() {
+            let from = self.checkpoint_path(n);
+            if from.exists() {
+                std::fs::rename(&from, self.checkpoint_path(n + 1))?;
+            }
+        }
+        // copied rather than renamed so path is never missing
+        std::fs::copy(&self.path, self.checkpoint_path(1))?;
+        return Ok(());
+    }
+}
+
+// the directory kept next to a database for its old copies
+pub const CHECKPOINT_DIR: &str = "checkpoints";
+
+// <dir of path>/checkpoints/<stem of path>.<tag>.<extension of path>
+pub fn checkpoint_path(path: &Path, tag: &str) -> PathBuf {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let name = match path.extension() {
+        Some(ext) => format!("{}.{}.{}", stem, tag, ext.to_string_lossy()),
+        None => format!("{}.{}", stem, tag),
+    };
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    return dir.join(CHECKPOINT_DIR).join(name);
+}
+
+impl Backend for CheckpointBackend {
+    fn get_data(&mut self) -> BackendResult<Vec<u8>> {
+        return Ok(std::fs::read(&self.path)?);
+    }
+
+    fn put_data(&mut self, data: &[u8]) -> BackendResult<()> {
+        if let Some((backup, bytes)) = &self.original {
+            if let Some(dir) = backup.parent() {
+                std::fs::create_dir_all(dir)?;
+            }
+            std::fs::write(backup, bytes)?;
+            self.original = None;
+        }
+        let temp = self.temp_path();
+        {
+            let mut file = File::create(&temp)?;
+            file.write_all(data)?;
+            file.sync_all()?;
+        }
+        self.rotate()?;
+        std::fs::rename(&temp, &self.path)?;
+        return Ok(());
+    }
+}
@@ -0,0 +1,54 @@
+use std::io::Read;
+use std::path::Path;
+
+use rustbreak::deser::{Bincode, DeSerializer, Ron};
+use rustbreak::error::DeSerResult;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+// how a database is written to disk. ron can be read and edited by hand,
+// bincode is far smaller and quicker to load and save for large bounds
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+    Ron,
+    Bincode,
+}
+
+impl Default for Encoding {
+    fn default() -> Self {
+        Encoding::Ron
+    }
+}
+
+impl Encoding {
+    // .bin files are bincode, anything else is treated as ron
+    pub fn from_path(path: &Path) -> Encoding {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("bin") => Encoding::Bincode,
+            _ => Encoding::Ron,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            Encoding::Ron => "ron",
+            Encoding::Bincode => "bin",
+        }
+    }
+}
+
+impl<T: Serialize + DeserializeOwned> DeSerializer<T> for Encoding {
+    fn serialize(&self, val: &T) -> DeSerResult<Vec<u8>> {
+        match self {
+            Encoding::Ron => Ron.serialize(val),
+            Encoding::Bincode => Bincode.serialize(val),
+        }
+    }
+
+    fn deserialize<R: Read>(&self, s: R) -> DeSerResult<T> {
+        match self {
+            Encoding::Ron => Ron.deserialize(s),
+            Encoding::Bincode => Bincode.deserialize(s),
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use serde::{Deserialize, Serialize};
+
+use crate::snake::{Action, Arena, DeathCause, TickOutcome};
+use crate::MyState;
+
+// a gym style interface so the game can be driven by any learner, not only
+// through the Trainer
+pub trait Environment {
+    type Observation;
+    type Action;
+
+    // starts a new episode, reseeding first if a seed is given
+    fn reset(&mut self, seed: Option<u64>) -> Self::Observation;
+
+    // returns (observation, reward, done, info). action is taken in the same
+    // frame as the last observation, so with symmetry on it is read as rotated
+    // or mirrored like the observation was. when done is true the environment
+    // has already started over, so the observation is the first one of the
+    // next episode and info describes the one that just ended
+    fn step(&mut self, action: Self::Action) -> (Self::Observation, f64, bool, Info);
+}
+
+// describes the episode as it stood after a step, or as it ended if that step was fatal
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Info {
+    // apples eaten
+    pub score: u32,
+    pub length: usize,
+    pub steps: u32,
+    pub death: Option<DeathCause>,
+}
+
+impl Environment for Arena {
+    type Observation = MyState;
+    type Action = Action;
+
+    fn reset(&mut self, seed: Option<u64>) -> MyState {
+        match seed {
+            Some(seed) => self.reseed(seed),
+            None => Arena::reset(self),
+        }
+        return self.state.clone();
+    }
+
+    fn step(&mut self, action: Action) -> (MyState, f64, bool, Info) {
+        let outcome = self.tick(self.world_action(action));
+        let reward = self.reward_for_last_action;
+        let info = match outcome {
+            TickOutcome::Died {
+                cause,
+                final_length,
+                steps,
+            } => Info {
+                score: self.score,
+                length: final_length,
+                steps: steps,
+                death: Some(cause),
+            },
+            _ => Info {
+                score: self.score,
+                length: self.snake.len(),
+                steps: self.steps,
+                death: None,
+            },
+        };
+        if self.finished() {
+            Arena::reset(self);
+        }
+        return (self.state.clone(), reward, outcome.died(), info);
+    }
+}
@@ -0,0 +1,136 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use crate::snake::{DeathCause, TickOutcome};
+use crate::trainer::Trainer;
+use crate::{load_from_db, new_arena, Config};
+
+// how a stored policy did over a batch of greedy episodes with one bound
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Evaluation {
+    pub bound: usize,
+    pub episodes: u32,
+    // length of the snake when each episode ended
+    pub mean_length: f64,
+    pub median_length: f64,
+    pub max_length: usize,
+    // apples eaten per 100 ticks, over every episode together
+    pub apples_per_100_steps: f64,
+    pub deaths: DeathCounts,
+    // share of ticks where the table had never seen the state, so the
+    // action was picked at random
+    pub unknown_state_rate: f64,
+}
+
+#[derive(Copy, Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct DeathCounts {
+    pub wall: u32,
+    pub body: u32,
+    pub starvation: u32,
+    // still alive when the episode was cut off at max_steps
+    pub survived: u32,
+}
+
+// plays episodes greedily from the table stored for config.bound without
+// learning anything. episode n is seeded with the arena's seed + n, so a
+// config with a fixed seed always gives the same result
+pub fn evaluate(config: &Config, episodes: u32, max_steps: u32) -> Evaluation {
+    let mut agent = new_arena(config);
+    let first_seed = agent.seed();
+
+    let mut trainer = Trainer::new();
+    load_from_db(&config.db, &mut trainer, config.bound);
+
+    let mut lengths = Vec::with_capacity(episodes as usize);
+    let mut deaths = DeathCounts::default();
+    let mut apples = 0;
+    let mut steps = 0;
+    let mut unknown = 0;
+
+    for episode in 0..episodes {
+        agent.reseed(first_seed.wrapping_add(episode as u64));
+        let mut length = None;
+        for _ in 0..max_steps {
+            let action = match trainer.best_action(&agent.key) {
+                Some(action) => action,
+                None => {
+                    unknown += 1;
+                    agent.random_action(&agent.key.actions())
+                }
+            };
+            steps += 1;
+            match agent.tick(agent.world_action(action)) {
+                TickOutcome::Moved => {}
+                TickOutcome::Ate => apples += 1,
+                TickOutcome::Died {
+                    cause,
+                    final_length,
+                    ..
+                } => {
+                    match cause {
+                        DeathCause::Wall => deaths.wall += 1,
+                        DeathCause::Body => deaths.body += 1,
+                        DeathCause::Starvation => deaths.starvation += 1,
+                    }
+                    length = Some(final_length);
+                    break;
+                }
+            }
+        }
+        if length.is_none() {
+            deaths.survived += 1;
+        }
+        lengths.push(length.unwrap_or(agent.snake.len()));
+    }
+
+    lengths.sort();
+    return Evaluation {
+        bound: config.bound,
+        episodes: episodes,
+        mean_length: lengths.iter().sum::<usize>() as f64 / lengths.len().max(1) as f64,
+        median_length: median(&lengths),
+        max_length: lengths.last().cloned().unwrap_or(0),
+        apples_per_100_steps: 100.0 * apples as f64 / steps.max(1) as f64,
+        deaths: deaths,
+        unknown_state_rate: unknown as f64 / steps.max(1) as f64,
+    };
+}
+
+// lengths must be sorted
+fn median(lengths: &[usize]) -> f64 {
+    if lengths.is_empty() {
+        return 0.0;
+    }
+    let middle = lengths.len() / 2;
+    if lengths.len() % 2 == 0 {
+        return (lengths[middle - 1] + lengths[middle]) as f64 / 2.0;
+    }
+    return lengths[middle] as f64;
+}
+
+impl fmt::Display for Evaluation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "bound {} over {} episodes", self.bound, self.episodes)?;
+        writeln!(
+            f,
+            "  length: mean {:.2}, median {:.1}, max {}",
+            self.mean_length, self.median_length, self.max_length
+        )?;
+        writeln!(
+            f,
+            "  apples per 100 steps: {:.2}",
+            self.apples_per_100_steps
+        )?;
+        writeln!(
+            f,
+            "  deaths: wall {}, body {}, starvation {}, survived {}",
+            self.deaths.wall, self.deaths.body, self.deaths.starvation, self.deaths.survived
+        )?;
+        write!(
+            f,
+            "  unknown states: {:.2}%",
+            100.0 * self.unknown_state_rate
+        )
+    }
+}
@@ -0,0 +1,102 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, Frame, ImageResult, Rgba, RgbaImage};
+
+use crate::render::Renderer;
+use crate::snake::Arena;
+
+// the same colours GliumRenderer draws with
+const BACKGROUND: Rgba<u8> = Rgba([0, 0, 0, 255]);
+const SNAKE: Rgba<u8> = Rgba([0, 128, 0, 255]);
+const APPLE: Rgba<u8> = Rgba([255, 0, 0, 255]);
+
+// draws the arena into an image in memory, each tile cell_size pixels wide.
+// y grows upwards in the arena, so it is flipped to match the window
+pub fn rasterize(arena: &Arena, cell_size: u32) -> RgbaImage {
+    let width = arena.arena_size.0 as u32 * cell_size;
+    let height = arena.arena_size.1 as u32 * cell_size;
+    let mut image = RgbaImage::from_pixel(width, height, BACKGROUND);
+
+    for chunk in arena.snake.iter() {
+        fill_tile(&mut image, arena, (chunk.0, chunk.1), cell_size, SNAKE);
+    }
+    if arena.apple_pos.2 {
+        fill_tile(
+            &mut image,
+            arena,
+            (arena.apple_pos.0, arena.apple_pos.1),
+            cell_size,
+            APPLE,
+        );
+    }
+    return image;
+}
+
+fn fill_tile(
+    image: &mut RgbaImage,
+    arena: &Arena,
+    tile: (i32, i32),
+    cell_size: u32,
+    color: Rgba<u8>,
+) {
+    let left = tile.0 as u32 * cell_size;
+    let top = (arena.arena_size.1 - 1 - tile.1) as u32 * cell_size;
+    for x in left..left + cell_size {
+        for y in top..top + cell_size {
+            image.put_pixel(x, y, color);
+        }
+    }
+}
+
+// a renderer that keeps every frame it is shown so an episode can be written
+// out afterwards as png frames or a single animated gif
+pub struct FrameRecorder {
+    cell_size: u32,
+    pub frames: Vec<RgbaImage>,
+}
+
+impl FrameRecorder {
+    pub fn new(cell_size: u32) -> FrameRecorder {
+        return FrameRecorder {
+            cell_size: cell_size,
+            frames: Vec::new(),
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // writes frame_0000.png, frame_0001.png, ... into dir
+    pub fn save_pngs(&self, dir: &Path) -> ImageResult<()> {
+        std::fs::create_dir_all(dir)?;
+        for (i, frame) in self.frames.iter().enumerate() {
+            frame.save(dir.join(format!("frame_{:04}.png", i)))?;
+        }
+        return Ok(());
+    }
+
+    // stitches every frame into one looping gif showing each for frame_ms
+    pub fn save_gif(&self, path: &Path, frame_ms: u32) -> ImageResult<()> {
+        let mut encoder = GifEncoder::new(BufWriter::new(File::create(path)?));
+        encoder.set_repeat(Repeat::Infinite)?;
+        for frame in self.frames.iter() {
+            encoder.encode_frame(Frame::from_parts(
+                frame.clone(),
+                0,
+                0,
+                Delay::from_numer_denom_ms(frame_ms, 1),
+            ))?;
+        }
+        return Ok(());
+    }
+}
+
+impl Renderer for FrameRecorder {
+    fn render(&mut self, arena: &Arena) {
+        self.frames.push(rasterize(arena, self.cell_size));
+    }
+}
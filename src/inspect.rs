@@ -0,0 +1,281 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+
+use serde::{Deserialize, Serialize};
+
+use crate::snake::Action;
+use crate::{MapState, MyState, PackedState, QTable, VisitTable};
+
+// how the action values in one table are spread
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ValueStats {
+    pub states: usize,
+    // state/action pairs
+    pub values: usize,
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+    pub median: f64,
+    // 10th and 90th percentiles
+    pub low: f64,
+    pub high: f64,
+}
+
+pub fn value_stats(table: &QTable) -> ValueStats {
+    let mut values: Vec<f64> = table
+        .values()
+        .flat_map(|actions| actions.values().cloned())
+        .collect();
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+
+    let percentile = |p: f64| {
+        if values.is_empty() {
+            return 0.0;
+        }
+        values[((values.len() - 1) as f64 * p).round() as usize]
+    };
+    return ValueStats {
+        states: table.len(),
+        values: values.len(),
+        min: values.first().cloned().unwrap_or(0.0),
+        max: values.last().cloned().unwrap_or(0.0),
+        mean: values.iter().sum::<f64>() / values.len().max(1) as f64,
+        median: percentile(0.5),
+        low: percentile(0.1),
+        high: percentile(0.9),
+    };
+}
+
+// the n states with the highest best action value, or the lowest if top is
+// false, along with that value
+pub fn ranked_states(table: &QTable, n: usize, top: bool) -> Vec<(PackedState, f64)> {
+    let mut ranked: Vec<(PackedState, f64)> = table
+        .iter()
+        .filter_map(|(state, actions)| {
+            let best = actions
+                .values()
+                .cloned()
+                .fold(None, |best: Option<f64>, v| {
+                    Some(best.map_or(v, |best| best.max(v)))
+                })?;
+            Some((*state, best))
+        })
+        .collect();
+    ranked.sort_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(std::cmp::Ordering::Equal));
+    if top {
+        ranked.reverse();
+    }
+    ranked.truncate(n);
+    return ranked;
+}
+
+// draws the window a state describes with y pointing up: @ is the head, #
+// is death and . is free. below it are the apple features and the value and
+// visit count of each action
+pub fn render_state(
+    state: &PackedState,
+    values: Option<&HashMap<Action, f64>>,
+    visits: Option<&HashMap<Action, u64>>,
+) -> String {
+    let unpacked = MyState::from(state);
+    let bound = unpacked.map.len();
+    let half = bound / 2;
+
+    let mut out = String::new();
+    for j in (0..bound).rev() {
+        for i in 0..bound {
+            let c = if i == half && j == half {
+                '@'
+            } else if unpacked.map[i][j] == MapState::Death {
+                '#'
+            } else {
+                '.'
+            };
+            out.push(c);
+        }
+        out.push('\n');
+    }
+    writeln!(
+        out,
+        "apple direction {:?}, distance {}, in window {}{}",
+        unpacked.curr_apple,
+        unpacked.apple_distance,
+        unpacked.apple_in_window,
+        if unpacked.relative {
+            ", relative to the heading"
+        } else {
+            ""
+        }
+    )
+    .unwrap();
+
+    for action in state.actions() {
+        match values.and_then(|values| values.get(&action)) {
+            Some(value) => writeln!(
+                out,
+                "{:?}: {} ({} visits)",
+                action,
+                value,
+                visits.and_then(|visits| visits.get(&action)).unwrap_or(&0)
+            )
+            .unwrap(),
+            None => writeln!(out, "{:?}: never tried", action).unwrap(),
+        }
+    }
+    return out;
+}
+
+// drops the values that still hold initial_value, meaning nothing was ever
+// learned for them, along with states left with no values at all. returns
+// how many values were removed
+pub fn prune_untouched(table: &mut QTable, visits: &mut VisitTable, initial_value: f64) -> usize {
+    return prune(table, visits, |value, _| value == initial_value);
+}
+
+// drops the values updated fewer than min_visits times. values from before
+// visits were counted have no count and are kept
+pub fn prune_rarely_visited(table: &mut QTable, visits: &mut VisitTable, min_visits: u64) -> usize {
+    return prune(table, visits, |_, count| {
+        count.map_or(false, |count| count < min_visits)
+    });
+}
+
+// count is None for values with no recorded visits
+fn prune<F: Fn(f64, Option<u64>) -> bool>(
+    table: &mut QTable,
+    visits: &mut VisitTable,
+    remove: F,
+) -> usize {
+    let mut removed = 0;
+    for (state, actions) in table.iter_mut() {
+        let mut state_visits = visits.get_mut(state);
+        let before = actions.len();
+        actions.retain(|action, value| {
+            let count = state_visits.as_ref().and_then(|m| m.get(action)).cloned();
+            if remove(*value, count) {
+                if let Some(state_visits) = state_visits.as_mut() {
+                    state_visits.remove(action);
+                }
+                return false;
+            }
+            return true;
+        });
+        removed += before - actions.len();
+    }
+    table.retain(|_, actions| !actions.is_empty());
+    visits.retain(|state, actions| !actions.is_empty() && table.contains_key(state));
+    return removed;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(distance: i32) -> PackedState {
+        let mut state = MyState::new(3);
+        state.apple_distance = distance;
+        return PackedState::from(&state);
+    }
+
+    fn table(rows: &[(i32, &[(Action, f64)])]) -> QTable {
+        return rows
+            .iter()
+            .map(|(distance, values)| (state(*distance), values.iter().cloned().collect()))
+            .collect();
+    }
+
+    fn visits(rows: &[(i32, &[(Action, u64)])]) -> VisitTable {
+        return rows
+            .iter()
+            .map(|(distance, counts)| (state(*distance), counts.iter().cloned().collect()))
+            .collect();
+    }
+
+    #[test]
+    fn stats_cover_every_value() {
+        let table = table(&[
+            (0, &[(Action::XPos, 1.0), (Action::YPos, 2.0)]),
+            (
+                1,
+                &[
+                    (Action::XPos, 3.0),
+                    (Action::XNeg, 4.0),
+                    (Action::YNeg, 5.0),
+                ],
+            ),
+        ]);
+        let stats = value_stats(&table);
+        assert_eq!(stats.states, 2);
+        assert_eq!(stats.values, 5);
+        assert_eq!((stats.min, stats.max), (1.0, 5.0));
+        assert_eq!((stats.mean, stats.median), (3.0, 3.0));
+        assert_eq!((stats.low, stats.high), (1.0, 5.0));
+    }
+
+    #[test]
+    fn stats_of_nothing_are_zero() {
+        let stats = value_stats(&HashMap::new());
+        assert_eq!(stats.values, 0);
+        assert_eq!((stats.min, stats.mean, stats.max), (0.0, 0.0, 0.0));
+    }
+
+    #[test]
+    fn ranks_states_by_their_best_value() {
+        let table = table(&[
+            (0, &[(Action::XPos, 1.0), (Action::YPos, 9.0)]),
+            (1, &[(Action::XPos, 3.0)]),
+            (2, &[(Action::XPos, -2.0)]),
+        ]);
+        assert_eq!(
+            ranked_states(&table, 2, true),
+            vec![(state(0), 9.0), (state(1), 3.0)]
+        );
+        assert_eq!(ranked_states(&table, 1, false), vec![(state(2), -2.0)]);
+    }
+
+    #[test]
+    fn renders_the_window_and_every_action() {
+        let mut unpacked = MyState::new(3);
+        unpacked.map[0][2] = MapState::Death;
+        let state = PackedState::from(&unpacked);
+        let values = vec![(Action::XPos, 1.5)].into_iter().collect();
+        let counts = vec![(Action::XPos, 4)].into_iter().collect();
+        let text = render_state(&state, Some(&values), Some(&counts));
+        assert!(text.starts_with("#..\n.@.\n...\n"));
+        assert!(text.contains("XPos: 1.5 (4 visits)"));
+        assert!(text.contains("YNeg: never tried"));
+    }
+
+    #[test]
+    fn prunes_untouched_values_and_empty_states() {
+        let mut table = table(&[
+            (0, &[(Action::XPos, 2.0), (Action::YPos, 1.0)]),
+            (1, &[(Action::XPos, 2.0)]),
+        ]);
+        let mut visits = visits(&[(1, &[(Action::XPos, 1)])]);
+        assert_eq!(prune_untouched(&mut table, &mut visits, 2.0), 2);
+        assert_eq!(table.len(), 1);
+        assert_eq!(table[&state(0)].len(), 1);
+        assert!(visits.is_empty());
+    }
+
+    #[test]
+    fn prunes_rarely_visited_values_but_not_uncounted_ones() {
+        let mut table = table(&[
+            (0, &[(Action::XPos, 1.0), (Action::YPos, 1.0)]),
+            (1, &[(Action::XPos, 1.0)]),
+            (2, &[(Action::XPos, 1.0)]),
+        ]);
+        let mut visits = visits(&[
+            (0, &[(Action::XPos, 5), (Action::YPos, 1)]),
+            (1, &[(Action::XPos, 2)]),
+        ]);
+        assert_eq!(prune_rarely_visited(&mut table, &mut visits, 3), 2);
+        assert_eq!(table.len(), 2);
+        assert!(table[&state(0)].contains_key(&Action::XPos));
+        // never counted, so there is nothing to judge it by
+        assert!(table.contains_key(&state(2)));
+        assert_eq!(visits.len(), 1);
+        assert_eq!(visits[&state(0)].len(), 1);
+    }
+}
@@ -0,0 +1,67 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::marker::PhantomData;
+
+use serde::de::{Deserializer, IgnoredAny, MapAccess, Visitor};
+use serde::Deserialize;
+
+use crate::snake::Action;
+use crate::{MapState, MyState, PackedState, QTable};
+
+// keys as they were first written, reward and all
+#[derive(Deserialize)]
+struct OldMyState {
+    map: Vec<Vec<MapState>>,
+    curr_apple: (i32, i32),
+    #[serde(rename = "reward")]
+    _reward: IgnoredAny,
+}
+
+// a map read into a list, so the old keys don't need to be hashable
+struct Entries<K, V>(Vec<(K, V)>);
+
+impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for Entries<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct EntriesVisitor<K, V>(PhantomData<(K, V)>);
+
+        impl<'de, K: Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for EntriesVisitor<K, V> {
+            type Value = Entries<K, V>;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a map")
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+                let mut entries = Vec::new();
+                while let Some(entry) = access.next_entry()? {
+                    entries.push(entry);
+                }
+                return Ok(Entries(entries));
+            }
+        }
+
+        deserializer.deserialize_map(EntriesVisitor(PhantomData))
+    }
+}
+
+type OldTables = HashMap<usize, Entries<OldMyState, HashMap<Action, f64>>>;
+
+// reads a ron database written in the original layout and returns its tables
+// in the current one, None if it isn't in that layout
+pub fn read_old_tables(bytes: &[u8]) -> Option<HashMap<usize, QTable>> {
+    let tables: OldTables = ron::de::from_reader(bytes).ok()?;
+    let mut out = HashMap::new();
+    for (bound, Entries(entries)) in tables {
+        let table: QTable = entries
+            .into_iter()
+            .map(|(old, values)| {
+                let mut state = MyState::new(old.map.len());
+                state.map = old.map;
+                state.curr_apple = old.curr_apple;
+                (PackedState::from(&state), values)
+            })
+            .collect();
+        out.insert(bound, table);
+    }
+    return Some(out);
+}
@@ -4,47 +4,103 @@ extern crate rust_lm;
 extern crate rustbreak;
 
 use std::collections::HashMap;
-use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{Arc, Mutex};
 
-use rurel::mdp::{Agent, State};
-use rurel::strategy::terminate::{FixedIterations, TerminationStrategy};
-use rurel::strategy::{explore::RandomExploration, learn::QLearning};
-use rurel::AgentTrainer;
-use rustbreak::backend::PathBackend;
-use rustbreak::{deser::Ron, Database, PathDatabase};
+use rustbreak::error::RustbreakError;
+use rustbreak::Database;
 use serde::{Deserialize, Serialize};
 
-mod snake;
+use checkpoint::CheckpointBackend;
+pub use encoding::Encoding;
+use export::FrameRecorder;
+use merge::{MergeError, MergePolicy};
+use metrics::{SharedMetrics, WindowMetrics};
+pub use packed::PackedState;
+use render::{GliumRenderer, Renderer};
+use replay::{Format, Recorder};
+use schema::{Envelope, SchemaError, SCHEMA_VERSION};
+use sharded::{ShardedTable, SharedTables};
+use terminal::{Input, TerminalRenderer};
+pub use trainer::QLearning;
+use trainer::{FixedIterations, NumGames, StopFlag, TimePassed, Trainer, TrainingStats};
+
+pub mod checkpoint;
+pub mod encoding;
+pub mod env;
+pub mod evaluate;
+pub mod export;
+pub mod inspect;
+mod legacy;
+pub mod merge;
+pub mod metrics;
+pub mod packed;
+pub mod policy;
+pub mod render;
+pub mod replay;
+pub mod schema;
+pub mod settings;
+pub mod sharded;
+pub mod snake;
+pub mod symmetry;
+pub mod terminal;
+pub mod trainer;
+
+// learned action values for every state seen with one bound
+pub type QTable = HashMap<PackedState, HashMap<snake::Action, f64>>;
+
+// how many times training has updated each value in a QTable
+pub type VisitTable = HashMap<PackedState, HashMap<snake::Action, u64>>;
+
+// one QTable per bound, see schema::Envelope
+pub type SharedDatabase = Arc<Mutex<TableDatabase>>;
+
+type TableDatabase = Database<Envelope, CheckpointBackend, Encoding>;
 
 pub struct Config {
     pub bound: usize,
     pub arena_size: (i32, i32),
+    // fixes apple placement and exploration so a run can be repeated exactly
+    pub seed: Option<u64>,
+    pub apple_features: AppleFeatures,
+    pub action_mode: ActionMode,
+    // share what is learned between states that are rotations or mirror
+    // images of each other, see symmetry::canonicalise
+    pub symmetry: bool,
+    pub rewards: RewardConfig,
+    pub starvation: Option<StarvationLimit>,
     pub learning: QLearning,
-    pub db: Arc<
-        Mutex<
-            Database<
-                HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>,
-                PathBackend,
-                Ron,
-            >,
-        >,
-    >,
+    pub db: SharedDatabase,
+    // where train_for_time reports each window, None to train quietly
+    pub metrics: Option<SharedMetrics>,
+    // live tables shared with other workers, see AiComponents. None trains
+    // into a private copy of what db holds
+    pub tables: Option<Arc<SharedTables>>,
 }
 
+fn new_arena(config: &Config) -> snake::Arena {
+    let mut arena = snake::Arena::new(config.arena_size, config.bound, config.seed);
+    arena.set_apple_features(config.apple_features);
+    arena.set_action_mode(config.action_mode);
+    arena.set_symmetric(config.symmetry);
+    arena.set_rewards(config.rewards);
+    arena.set_starvation(config.starvation);
+    return arena;
+}
+
+// trains one bound. with Config.tables set, any number of these can train
+// the same bound at once, each updating the shared table in place, and it is
+// up to whoever owns the tables to snapshot them into the database.
+// otherwise the private table is copied into the database after every
+// window, and saving it to disk is still up to the caller
 pub struct AiComponents {
     config: Config,
-    trainer: AgentTrainer<MyState>,
+    trainer: Trainer,
     agent: snake::Arena,
-    db: Arc<
-        Mutex<
-            Database<
-                HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>,
-                PathBackend,
-                Ron,
-            >,
-        >,
-    >,
+    db: SharedDatabase,
+    // raised from outside to end training early
+    stop: Arc<AtomicBool>,
 }
 
 impl AiComponents {
@@ -52,18 +108,44 @@ impl AiComponents {
         if config.bound % 2 == 0 {
             panic!("Config.bound must be odd");
         }
-        let agent = snake::Arena::new(config.arena_size, config.bound);
+        let agent = new_arena(&config);
 
         let db = config.db.clone();
 
-        let mut out = AiComponents {
+        // training into tables keyed by differently built states would only
+        // corrupt them
+        db.lock()
+            .unwrap()
+            .write(|db| {
+                db.check_state_encoding(&config)?;
+                db.record_training(&config);
+                return Ok(());
+            })
+            .unwrap()
+            .unwrap_or_else(|e: SchemaError| panic!("{}", e));
+
+        let table = match &config.tables {
+            Some(tables) => tables.bound(config.bound),
+            None => Arc::new(ShardedTable::from_tables(
+                read_table(&db, config.bound),
+                read_visits(&db, config.bound),
+                sharded::DEFAULT_SHARDS,
+            )),
+        };
+
+        return AiComponents {
             config: config,
-            trainer: AgentTrainer::new(),
+            trainer: Trainer::with_table(table),
             agent: agent,
             db: db,
+            stop: Arc::new(AtomicBool::new(false)),
         };
-        out.reload_db_to_trainer();
-        return out;
+    }
+
+    // once stop is set, train_for_time saves what it has learned and returns
+    // within a tick or so instead of finishing its window
+    pub fn set_stop_flag(&mut self, stop: Arc<AtomicBool>) {
+        self.stop = stop;
     }
 
     pub fn train(&mut self) {
@@ -71,17 +153,24 @@ impl AiComponents {
     }
 
     pub fn train_for_time(&mut self, minutes: u32) {
-        let rand_explore = &RandomExploration::new();
         let mut i = 0;
         loop {
-            self.reload_db_to_trainer();
-            self.trainer.train(
+            let started = std::time::Instant::now();
+            let mut stats = self.trainer.train(
                 &mut self.agent,
                 &self.config.learning,
-                &mut TimePassed::new(std::time::Duration::from_secs(60)),
-                rand_explore,
+                &mut StopFlag::new(
+                    TimePassed::new(std::time::Duration::from_secs(60)),
+                    self.stop.clone(),
+                ),
             );
-            self.test_and_train();
+            if self.stop.load(Ordering::SeqCst) {
+                self.snapshot_private_table();
+                return;
+            }
+            stats.merge(self.test_and_train());
+            self.snapshot_private_table();
+            self.report_window(i, stats, started.elapsed());
 
             i += 1;
             if i == minutes {
@@ -90,41 +179,71 @@ impl AiComponents {
         }
     }
 
-    fn test_and_train(&mut self) {
+    fn test_and_train(&mut self) -> TrainingStats {
+        let mut stats = TrainingStats::default();
         loop {
-            if let Some(action) = self.trainer.best_action(self.agent.current_state()) {
-                if self.agent.tick(action) {
-                    return;
+            if self.stop.load(Ordering::SeqCst) {
+                return stats;
+            }
+            if let Some(action) = self.trainer.best_action(&self.agent.key) {
+                stats.ticks += 1;
+                let outcome = self.agent.tick(self.agent.world_action(action));
+                if let snake::TickOutcome::Died { final_length, .. } = outcome {
+                    stats.add_episode(final_length);
+                    self.agent.reset();
+                    return stats;
                 }
             } else {
-                self.trainer.train(
+                stats.merge(self.trainer.train(
                     &mut self.agent,
                     &self.config.learning,
                     &mut NumGames::new(1),
-                    &RandomExploration::new(),
-                );
-                self.save_to_db_from_trainer();
-                return;
+                ));
+                return stats;
             }
         }
     }
 
-    fn reload_db_to_trainer(&mut self) {
-        load_from_db(&self.db, &mut self.trainer, self.config.bound);
+    fn report_window(&self, window: u32, stats: TrainingStats, wall_time: std::time::Duration) {
+        let metrics = match &self.config.metrics {
+            Some(metrics) => metrics,
+            None => return,
+        };
+        metrics
+            .lock()
+            .unwrap()
+            .write(&WindowMetrics {
+                bound: self.config.bound,
+                window: window,
+                episodes: stats.episodes,
+                average_length: stats.average_length(),
+                table_size: self.trainer.table_size(),
+                new_states: stats.new_states,
+                wall_time_secs: wall_time.as_secs_f64(),
+            })
+            .unwrap();
     }
-    fn save_to_db_from_trainer(&mut self) {
-        save_to_db(&self.db, &mut self.trainer, self.config.bound);
+
+    fn snapshot_private_table(&self) {
+        if self.config.tables.is_none() {
+            write_table(
+                &self.db,
+                self.config.bound,
+                Some(self.trainer.table().snapshot()),
+            );
+        }
     }
 }
 
 pub fn test(mut config: Config) {
     let event_loop = glium::glutin::event_loop::EventLoop::new();
-    let mut agent = snake::Arena::new_render(config.arena_size, config.bound, &event_loop);
+    let mut renderer = GliumRenderer::new(config.arena_size, &event_loop);
+    let mut agent = new_arena(&config);
     let mut curr_action = snake::Action::YPos;
 
     let db = config.db.clone();
 
-    let mut trainer = AgentTrainer::new();
+    let mut trainer = Trainer::new();
     load_from_db(&db, &mut trainer, config.bound);
     let mut time_start = std::time::Instant::now();
 
@@ -185,113 +304,315 @@ pub fn test(mut config: Config) {
             std::time::Instant::now() + std::time::Duration::from_nanos(16_666_667 * 2);
         *control_flow = glium::glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
 
-        if let Some(action) = trainer.best_action(&agent.state) {
-            curr_action = action;
+        if let Some(action) = trainer.best_action(&agent.key) {
+            curr_action = agent.world_action(action);
         } else {
-            trainer.train(
-                &mut agent,
-                &config.learning,
-                &mut FixedIterations::new(1),
-                &RandomExploration::new(),
-            );
+            trainer.train(&mut agent, &config.learning, &mut FixedIterations::new(1));
             save_to_db(&db, &mut trainer, config.bound);
         }
 
-        if agent.tick(curr_action) || time_start.elapsed().as_secs() > 60 {
+        let died = agent.tick(curr_action).died();
+        if died {
+            agent.reset();
+        }
+        if died || time_start.elapsed().as_secs() > 60 {
             load_from_db(&db, &mut trainer, config.bound);
             time_start = std::time::Instant::now();
         }
+        renderer.render(&agent);
     });
 }
 
-pub fn get_database() -> Arc<
-    Mutex<
-        Database<HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>, PathBackend, Ron>,
-    >,
-> {
-    let db = Arc::new(
-        Mutex::new(
-            PathDatabase::<
-                HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>,
-                Ron,
-            >::load_from_path_or(
-                "snake_ai_database.ron".parse().unwrap(), HashMap::default(),
+// same as test() but drawn in the terminal, advancing one tick every tick_rate
+pub fn test_terminal(mut config: Config, tick_rate: std::time::Duration) {
+    let mut renderer = TerminalRenderer::new().unwrap();
+    let mut agent = new_arena(&config);
+    let mut curr_action = snake::Action::YPos;
+
+    let db = config.db.clone();
+
+    let mut trainer = Trainer::new();
+    load_from_db(&db, &mut trainer, config.bound);
+    let mut time_start = std::time::Instant::now();
+
+    loop {
+        let next_frame_time = std::time::Instant::now() + tick_rate;
+        while let Some(timeout) = next_frame_time.checked_duration_since(std::time::Instant::now())
+        {
+            match renderer.poll_input(timeout).unwrap() {
+                Some(Input::Bound(bound)) => {
+                    config.bound = bound;
+                    agent.new_bound(bound);
+                }
+                Some(Input::Reload) => {
+                    load_from_db(&db, &mut trainer, config.bound);
+                    agent.reset();
+                }
+                Some(Input::Quit) => return,
+                Some(Input::Action(_)) | None => {}
+            }
+        }
+
+        if let Some(action) = trainer.best_action(&agent.key) {
+            curr_action = agent.world_action(action);
+        } else {
+            trainer.train(&mut agent, &config.learning, &mut FixedIterations::new(1));
+            save_to_db(&db, &mut trainer, config.bound);
+        }
+
+        let died = agent.tick(curr_action).died();
+        if died {
+            agent.reset();
+        }
+        if died || time_start.elapsed().as_secs() > 60 {
+            load_from_db(&db, &mut trainer, config.bound);
+            time_start = std::time::Instant::now();
+        }
+        renderer.render(&agent);
+    }
+}
+
+// plays episodes greedily from the stored policy and writes each one to
+// out_dir as episode_<n>.gif along with an episode_<n>.ron recording that can
+// be replayed. states the policy has never seen get a random action, and an
+// episode that is still going after max_steps ticks is cut off. with pngs set
+// every frame is also written to episode_<n>/frame_<i>.png
+pub fn record(config: Config, episodes: u32, max_steps: u32, out_dir: &Path, pngs: bool) {
+    std::fs::create_dir_all(out_dir).unwrap();
+    let mut agent = new_arena(&config);
+    let mut frames = FrameRecorder::new(16);
+    let first_seed = agent.seed();
+
+    let mut trainer = Trainer::new();
+    load_from_db(&config.db, &mut trainer, config.bound);
+
+    for episode in 0..episodes {
+        let seed = first_seed.wrapping_add(episode as u64);
+        let mut recorder = Recorder::start(&mut agent, seed);
+        frames.clear();
+        frames.render(&agent);
+        for _ in 0..max_steps {
+            let action = match trainer.best_action(&agent.key) {
+                Some(action) => agent.world_action(action),
+                None => {
+                    let action = agent.random_action(&agent.key.actions());
+                    agent.world_action(action)
+                }
+            };
+            if recorder.tick(&mut agent, action).died() {
+                break;
+            }
+            frames.render(&agent);
+        }
+        frames
+            .save_gif(&out_dir.join(format!("episode_{}.gif", episode)), 100)
+            .unwrap();
+        if pngs {
+            frames
+                .save_pngs(&out_dir.join(format!("episode_{}", episode)))
+                .unwrap();
+        }
+        recorder
+            .finish()
+            .save(
+                &out_dir.join(format!("episode_{}.ron", episode)),
+                Format::Ron,
             )
-                .unwrap(),
-        ),
-    );
+            .unwrap();
+    }
+}
+
+// the encoding is picked from the file extension, see Encoding::from_path.
+// every save keeps the last few versions as checkpoints, see CheckpointBackend
+pub fn open_database(path: &Path, checkpoints: usize) -> SharedDatabase {
+    let db = load_database(path, checkpoints).unwrap_or_else(|e| panic!("{}", e));
+    return Arc::new(Mutex::new(db));
+}
+
+// opens path, creating it if it isn't there yet. a database written by an
+// older version is migrated in memory, and its original bytes are copied to
+// checkpoints/<name>.v<old version>.<extension> next to it when it is first
+// saved in the current layout
+fn load_database(path: &Path, checkpoints: usize) -> Result<TableDatabase, DatabaseError> {
+    let (mut backend, created) = CheckpointBackend::open(path, checkpoints)?;
+    let bytes = std::fs::read(path)?;
+    let envelope = read_envelope(path, &bytes)?;
+    let empty = envelope.is_none();
+    let envelope = match envelope {
+        Some((version, envelope)) => {
+            if version < SCHEMA_VERSION {
+                let backup = checkpoint::checkpoint_path(path, &format!("v{}", version));
+                backend.keep_before_saving(backup, bytes);
+            }
+            envelope
+        }
+        None => Envelope::default(),
+    };
+    let db = Database::from_parts(envelope, backend, Encoding::from_path(path));
+    if created || empty {
+        db.save()?;
+    }
+    return Ok(db);
+}
+
+// what an existing database holds, in the current layout. unlike
+// open_database this never creates or rewrites path, a missing file is an
+// error
+pub fn read_database(path: &Path) -> Result<Envelope, DatabaseError> {
+    CheckpointBackend::open_existing(path, 0)?;
+    let envelope = read_envelope(path, &std::fs::read(path)?)?;
+    return Ok(envelope.map(|(_, envelope)| envelope).unwrap_or_default());
+}
+
+// rewrites the database at from into to, each in the encoding its extension asks for
+pub fn convert_database(from: &Path, to: &Path) -> Result<(), DatabaseError> {
+    let envelope = read_database(from)?;
+    return write_database(to, envelope);
+}
+
+// combines the databases at inputs into output, which may be one of them.
+// every input has to exist
+pub fn merge_databases(
+    inputs: &[&Path],
+    output: &Path,
+    policy: MergePolicy,
+) -> Result<(), DatabaseError> {
+    let mut envelopes = vec![];
+    for input in inputs {
+        envelopes.push(read_database(input)?);
+    }
+    let merged = merge::merge_envelopes(envelopes, policy).map_err(DatabaseError::Merge)?;
+    return write_database(output, merged);
+}
+
+// replaces whatever is at path with envelope
+fn write_database(path: &Path, envelope: Envelope) -> Result<(), DatabaseError> {
+    let (backend, _) = CheckpointBackend::open(path, 0)?;
+    Database::from_parts(envelope, backend, Encoding::from_path(path)).save()?;
+    return Ok(());
+}
+
+// bytes, read from path, in any layout it was ever written in along with
+// the version they were found in, None if the file is empty. see
+// schema::migrate
+fn read_envelope(path: &Path, bytes: &[u8]) -> Result<Option<(u32, Envelope)>, DatabaseError> {
+    if bytes.is_empty() {
+        return Ok(None);
+    }
+    let migrated = schema::migrate(bytes, Encoding::from_path(path))
+        .map_err(|e| DatabaseError::Schema(path.to_path_buf(), e))?;
+    return Ok(Some(migrated));
+}
+
+#[derive(Debug)]
+pub enum DatabaseError {
+    Io(std::io::Error),
+    Storage(RustbreakError),
+    // the file isn't a database this version can read
+    Schema(PathBuf, SchemaError),
+    Merge(MergeError),
+}
+
+impl std::fmt::Display for DatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DatabaseError::Io(e) => write!(f, "io error: {}", e),
+            DatabaseError::Storage(e) => write!(f, "database error: {}", e),
+            DatabaseError::Schema(path, e) => write!(f, "{}: {}", path.display(), e),
+            DatabaseError::Merge(e) => write!(f, "can't merge: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for DatabaseError {}
+
+impl From<std::io::Error> for DatabaseError {
+    fn from(e: std::io::Error) -> Self {
+        DatabaseError::Io(e)
+    }
+}
+
+impl From<RustbreakError> for DatabaseError {
+    fn from(e: RustbreakError) -> Self {
+        DatabaseError::Storage(e)
+    }
+}
 
-    {
-        let db_real = db.lock().unwrap();
-        db_real.load().unwrap();
+impl From<rustbreak::error::BackendError> for DatabaseError {
+    fn from(e: rustbreak::error::BackendError) -> Self {
+        DatabaseError::Storage(e.into())
     }
+}
 
-    return db;
-}
-
-fn save_to_db(
-    db: &Arc<
-        Mutex<
-            Database<
-                HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>,
-                PathBackend,
-                Ron,
-            >,
-        >,
-    >,
-    trainer: &mut AgentTrainer<MyState>,
-    bound: usize,
-) {
+fn save_to_db(db: &SharedDatabase, trainer: &mut Trainer, bound: usize) {
     let db_real = db.lock().unwrap();
 
     db_real
         .write(|db| {
             let exported = trainer.export_learned_values();
-            if let Some(table) = db.get_mut(&bound) {
-                for key in exported.keys() {
-                    if let Some(value) = table.get_mut(key) {
-                        for fin_key in exported[key].keys() {
-                            value.insert(*fin_key, exported[key][fin_key]);
-                        }
-                    } else {
-                        table.insert(key.clone(), exported[key].clone());
-                    }
-                }
-            } else {
-                db.insert(bound, exported);
-            }
+            merge::overwrite(db.tables.entry(bound).or_default(), exported);
+            let visits = trainer.export_visits();
+            merge::overwrite(db.visits.entry(bound).or_default(), visits);
         })
         .unwrap();
 }
 
-fn load_from_db(
-    db: &Arc<
-        Mutex<
-            Database<
-                HashMap<usize, HashMap<MyState, HashMap<snake::Action, f64>>>,
-                PathBackend,
-                Ron,
-            >,
-        >,
-    >,
-    trainer: &mut AgentTrainer<MyState>,
-    bound: usize,
-) {
+fn load_from_db(db: &SharedDatabase, trainer: &mut Trainer, bound: usize) {
+    trainer.import_tables(read_table(db, bound), read_visits(db, bound));
+}
+
+// a copy of the table for bound, empty if nothing has been learned for it
+pub fn read_table(db: &SharedDatabase, bound: usize) -> QTable {
     let db_real = db.lock().unwrap();
     let mut vals = HashMap::new();
     db_real
         .read(|db| {
-            if let Some(tmp_vals) = db.get(&bound) {
+            if let Some(tmp_vals) = db.tables.get(&bound) {
                 vals = tmp_vals.clone();
             }
         })
         .unwrap();
-    trainer.import_state(vals);
+    return vals;
+}
+
+// a copy of the visit counts for bound, empty if none were recorded
+pub fn read_visits(db: &SharedDatabase, bound: usize) -> VisitTable {
+    let db_real = db.lock().unwrap();
+    let mut visits = HashMap::new();
+    db_real
+        .read(|db| {
+            if let Some(tmp_visits) = db.visits.get(&bound) {
+                visits = tmp_visits.clone();
+            }
+        })
+        .unwrap();
+    return visits;
+}
+
+// replaces the table and visit counts for bound outright, or drops them for
+// None. unlike save_to_db nothing already stored is kept. the database still
+// has to be saved afterwards
+pub fn write_table(db: &SharedDatabase, bound: usize, table: Option<(QTable, VisitTable)>) {
+    let db_real = db.lock().unwrap();
+    db_real
+        .write(|db| match table {
+            Some((table, visits)) => {
+                db.tables.insert(bound, table);
+                db.visits.insert(bound, visits);
+            }
+            None => {
+                db.tables.remove(&bound);
+                db.visits.remove(&bound);
+                db.metadata.before_apple_fix.retain(|b| *b != bound);
+            }
+        })
+        .unwrap();
 }
 
 pub fn play_human(config: Config) {
     let event_loop = glium::glutin::event_loop::EventLoop::new();
-    let mut game = snake::Arena::new_render(config.arena_size, config.bound, &event_loop);
+    let mut renderer = GliumRenderer::new(config.arena_size, &event_loop);
+    let mut game = new_arena(&config);
     let mut curr_action = snake::Action::YPos;
 
     event_loop.run(move |event, _, control_flow| {
@@ -337,25 +658,35 @@ pub fn play_human(config: Config) {
             std::time::Instant::now() + std::time::Duration::from_nanos(16_666_667 * 4);
         *control_flow = glium::glutin::event_loop::ControlFlow::WaitUntil(next_frame_time);
 
-        game.tick(curr_action);
+        if game.tick(curr_action).died() {
+            game.reset();
+        }
+        renderer.render(&game);
     });
 }
 
-#[derive(Copy, Clone, Debug, Serialize, Deserialize)]
-pub enum Fake {
-    Val(f64),
-}
-
-impl PartialEq for Fake {
-    fn eq(&self, _other: &Self) -> bool {
-        return true;
-    }
-}
+// same as play_human() but drawn in the terminal, advancing one tick every tick_rate
+pub fn play_human_terminal(config: Config, tick_rate: std::time::Duration) {
+    let mut renderer = TerminalRenderer::new().unwrap();
+    let mut game = new_arena(&config);
+    let mut curr_action = snake::Action::YPos;
 
-impl Eq for Fake {}
+    loop {
+        let next_frame_time = std::time::Instant::now() + tick_rate;
+        while let Some(timeout) = next_frame_time.checked_duration_since(std::time::Instant::now())
+        {
+            match renderer.poll_input(timeout).unwrap() {
+                Some(Input::Action(action)) => curr_action = action,
+                Some(Input::Quit) => return,
+                Some(Input::Bound(_)) | Some(Input::Reload) | None => {}
+            }
+        }
 
-impl Hash for Fake {
-    fn hash<H: Hasher>(&self, _state: &mut H) {}
+        if game.tick(curr_action).died() {
+            game.reset();
+        }
+        renderer.render(&game);
+    }
 }
 
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
@@ -364,13 +695,124 @@ pub enum MapState {
     Death,
 }
 
+// which facts about the apple make it into MyState. features that are turned
+// off are left at a constant so they don't split the table
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct AppleFeatures {
+    // sign of the x and y offset from the head to the apple
+    pub direction: bool,
+    // manhattan distance to the apple divided by this (positive) bucket width
+    pub distance_bucket: Option<i32>,
+    // whether the apple lies inside the bound x bound window around the head
+    pub in_window: bool,
+}
+
+impl Default for AppleFeatures {
+    fn default() -> Self {
+        AppleFeatures {
+            direction: true,
+            distance_bucket: None,
+            in_window: false,
+        }
+    }
+}
+
+// Absolute lets the snake move along either axis (including straight back into
+// itself). Relative rotates MyState to the snake's heading and only offers
+// turning left, carrying on or turning right
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ActionMode {
+    Absolute,
+    Relative,
+}
+
+impl Default for ActionMode {
+    fn default() -> Self {
+        ActionMode::Absolute
+    }
+}
+
+// what the arena pays out for each tick. the first three are always applied,
+// the rest shape the reward on ticks where nothing was eaten and are off (0.0
+// or None) by default
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RewardConfig {
+    pub death: f64,
+    pub apple: f64,
+    pub step: f64,
+    // added for moving closer to the apple (manhattan), taken away otherwise
+    pub closer_to_apple: f64,
+    // taken away for moving onto a tile already visited since the last apple
+    pub revisit_penalty: f64,
+    // times the snake's length, added on top of apple
+    pub length_bonus: f64,
+    // after this many ticks without an apple every further tick costs
+    // hunger_penalty
+    pub hunger_limit: Option<u32>,
+    pub hunger_penalty: f64,
+    // paid instead of death when the snake hits its StarvationLimit
+    pub starvation: f64,
+}
+
+impl Default for RewardConfig {
+    fn default() -> Self {
+        RewardConfig {
+            death: -4.0,
+            apple: 4.0,
+            step: -0.01,
+            closer_to_apple: 0.0,
+            revisit_penalty: 0.0,
+            length_bonus: 0.0,
+            hunger_limit: None,
+            hunger_penalty: 0.0,
+            starvation: -4.0,
+        }
+    }
+}
+
+// ends an episode once the snake has gone this many ticks without eating, so
+// a policy that circles forever can't stall training. the limit is
+// steps + per_area * width * height + per_length * snake length
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StarvationLimit {
+    pub steps: u32,
+    pub per_area: f64,
+    pub per_length: f64,
+}
+
+impl StarvationLimit {
+    pub fn ticks(&self, arena_size: (i32, i32), length: usize) -> u32 {
+        let area = (arena_size.0 * arena_size.1) as f64;
+        return self.steps + (self.per_area * area + self.per_length * length as f64) as u32;
+    }
+}
+
+impl Default for StarvationLimit {
+    fn default() -> Self {
+        StarvationLimit {
+            steps: 0,
+            per_area: 1.0,
+            per_length: 0.0,
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct MyState {
     // is the given tile Death
     pub map: Vec<Vec<MapState>>,
     // indicates the direction towards the apple
     pub curr_apple: (i32, i32),
-    pub reward: Fake,
+    #[serde(default)]
+    pub apple_distance: i32,
+    #[serde(default)]
+    pub apple_in_window: bool,
+    // seen from the snake's heading with relative actions, see ActionMode
+    #[serde(default)]
+    pub relative: bool,
 }
 
 impl MyState {
@@ -385,82 +827,87 @@ impl MyState {
         MyState {
             map: map,
             curr_apple: (0, 0),
-            reward: Fake::Val(0.0),
+            apple_distance: 0,
+            apple_in_window: false,
+            relative: false,
         }
     }
 }
 
-impl State for MyState {
-    type A = snake::Action;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    fn reward(&self) -> f64 {
-        let Fake::Val(v) = self.reward;
-        return v;
-    }
+    const VERSION_0: &str = "{3: {(map: [[Empty, Empty, Empty], [Empty, Death, Empty], \
+                             [Empty, Empty, Empty]], curr_apple: (1, -1), reward: Val(-0.01)): \
+                             {XPos: 1.5}}}";
 
-    fn actions(&self) -> Vec<Self::A> {
-        vec![
-            snake::Action::XPos,
-            snake::Action::YPos,
-            snake::Action::XNeg,
-            snake::Action::YNeg,
-        ]
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("snake_ai_{}_{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        return dir;
     }
-}
 
-impl Agent<MyState> for snake::Arena {
-    fn current_state(&self) -> &MyState {
-        return &self.state;
+    #[test]
+    fn reading_an_old_database_leaves_it_alone() {
+        let dir = temp_dir("read_old");
+        let path = dir.join("old.ron");
+        std::fs::write(&path, VERSION_0).unwrap();
+
+        let envelope = read_database(&path).unwrap();
+        assert_eq!(envelope.version, SCHEMA_VERSION);
+        assert_eq!(envelope.tables[&3].len(), 1);
+        convert_database(&path, &dir.join("new.bin")).unwrap();
+        merge_databases(&[&path], &dir.join("merged.ron"), MergePolicy::Average).unwrap();
+        open_database(&path, 2);
+
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), VERSION_0);
+        assert!(!dir.join(checkpoint::CHECKPOINT_DIR).exists());
+        assert_eq!(read_database(&dir.join("new.bin")).unwrap(), envelope);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 
-    fn take_action(&mut self, action: &<MyState as State>::A) {
-        self.tick(*action);
-    }
-}
-
-struct TimePassed {
-    termination_time: std::time::Instant,
-}
-
-impl TimePassed {
-    pub fn new(time_to_train: std::time::Duration) -> TimePassed {
-        TimePassed {
-            termination_time: std::time::Instant::now() + time_to_train,
-        }
+    #[test]
+    fn saving_an_old_database_keeps_the_original() {
+        let dir = temp_dir("save_old");
+        let path = dir.join("old.ron");
+        std::fs::write(&path, VERSION_0).unwrap();
+
+        open_database(&path, 0).lock().unwrap().save().unwrap();
+        let backup = checkpoint::checkpoint_path(&path, "v0");
+        assert_eq!(std::fs::read_to_string(&backup).unwrap(), VERSION_0);
+        assert_eq!(read_database(&path).unwrap().tables[&3].len(), 1);
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-impl<S: State> TerminationStrategy<S> for TimePassed {
-    fn should_stop(&mut self, _state: &S) -> bool {
-        if let Option::Some(_) =
-            std::time::Instant::now().checked_duration_since(self.termination_time)
-        {
-            true
-        } else {
-            false
-        }
-    }
-}
-
-struct NumGames {
-    curr_game: u32,
-    target_games: u32,
-}
-
-impl NumGames {
-    pub fn new(target_games: u32) -> NumGames {
-        return NumGames {
-            curr_game: 0,
-            target_games: target_games,
+    #[test]
+    fn old_tables_are_flagged_until_deleted() {
+        let dir = temp_dir("flagged");
+        let path = dir.join("old.ron");
+        std::fs::write(&path, VERSION_0).unwrap();
+
+        let db = open_database(&path, 0);
+        let flagged = || {
+            db.lock()
+                .unwrap()
+                .read(|db| db.metadata.before_apple_fix.clone())
+                .unwrap()
         };
+        assert_eq!(flagged(), vec![3]);
+        assert_eq!(read_table(&db, 3).len(), 1);
+        write_table(&db, 3, None);
+        assert!(flagged().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
-}
 
-impl<S: State> TerminationStrategy<S> for NumGames {
-    fn should_stop(&mut self, state: &S) -> bool {
-        if state.reward() < -0.5 {
-            self.curr_game += 1;
-        }
-        return self.curr_game == self.target_games;
+    #[test]
+    fn reading_a_missing_database_is_an_error() {
+        let dir = temp_dir("missing");
+        let path = dir.join("missing.ron");
+        assert!(read_database(&path).is_err());
+        assert!(convert_database(&path, &dir.join("out.bin")).is_err());
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).unwrap();
     }
 }
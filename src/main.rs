@@ -1,52 +1,439 @@
+use std::fs::File;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::thread;
 
-use rurel::strategy::learn::QLearning;
+use snake_ai::evaluate::evaluate;
+use snake_ai::inspect::{
+    prune_rarely_visited, prune_untouched, ranked_states, render_state, value_stats,
+};
+use snake_ai::merge::MergePolicy;
+use snake_ai::metrics::MetricsSink;
+use snake_ai::policy::PolicyStore;
+use snake_ai::replay::{Recording, Replay};
+use snake_ai::settings::Settings;
+use snake_ai::sharded::{SharedTables, DEFAULT_SHARDS};
+use snake_ai::terminal::TerminalRenderer;
+use snake_ai::{
+    convert_database, merge_databases, open_database, play_human, play_human_terminal,
+    read_database, read_table, read_visits, record, test, test_terminal, write_table, AiComponents,
+    PackedState, QTable, SharedDatabase, VisitTable,
+};
 
-use snake_ai::{get_database, test, AiComponents, Config};
+const USAGE: &str = "usage: snake_ai [--config <file>] [--<setting> <value>]... <command>
+
+commands:
+    train                                   train every bound in parallel
+    watch [terminal]                        watch the first bound play
+    play [terminal]                         play yourself
+    evaluate [episodes] [json output file]  greedy statistics for every bound
+    record [episodes] [output directory] [png]
+                                            save gifs and recordings of the first bound,
+                                            and every frame as a png if asked
+    replay <recording>                      play a recording back in the terminal
+    db                                      show what the database (or every policy) holds
+    db convert <from> <to>                  rewrite a database as ron (.ron) or bincode (.bin)
+    db merge <policy> <output> <input>...   combine databases, settling values present in more
+                                            than one by last, average, weighted or max
+    db stats                                value statistics for every bound
+    db top <bound> [n]                      the n states with the highest values
+    db bottom <bound> [n]                   the n states with the lowest values
+    db show <bound> <map> <features>        draw one state and its action values
+    db delete <bound>                       drop everything learned for a bound
+    db prune [bound]                        drop values never moved from the initial value
+    db prune-rare <min visits> [bound]      drop values updated fewer than min visits times
+
+settings are read from snake_ai.toml unless --config names another file. any
+of them can be overridden, e.g. --bounds [3,5] --learning.alpha 0.2
+--starvation.per_area 1 --seed none. --database policies --policy baseline
+learns into policies/baseline.ron";
 
 fn main() {
-    let mut handles = vec![];
-    let db = get_database();
+    let mut config_path = None;
+    let mut overrides = vec![];
+    let mut positional = vec![];
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        if let Some(key) = arg.strip_prefix("--") {
+            let value = args.next().unwrap_or_else(|| usage_error(&arg));
+            if key == "config" {
+                config_path = Some(value);
+            } else {
+                overrides.push((key.to_string(), value));
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    let mut settings = Settings::load(config_path.as_ref().map(Path::new)).unwrap_or_else(fail);
+    for (key, value) in overrides.iter() {
+        settings.set(key, value).unwrap_or_else(fail);
+    }
 
-    for i in 2..10 {
-        if i % 2 == 0 {
-            continue;
+    let command = positional.get(0).map(|s| s.as_str()).unwrap_or("");
+    let args = &positional[positional.len().min(1)..];
+    match command {
+        "train" => train(&settings),
+        "watch" | "play" => {
+            let config = settings.config(settings.bounds[0], open_policy(&settings), None);
+            let tick_rate = std::time::Duration::from_millis(settings.tick_ms);
+            match (command, args.get(0).map(|s| s.as_str())) {
+                ("watch", None) => test(config),
+                ("watch", Some("terminal")) => test_terminal(config, tick_rate),
+                ("play", None) => play_human(config),
+                ("play", Some("terminal")) => play_human_terminal(config, tick_rate),
+                _ => usage_error(&args[0]),
+            }
+        }
+        "evaluate" => {
+            let episodes = args
+                .get(0)
+                .map(|s| s.parse().expect("episodes must be a number"))
+                .unwrap_or(100);
+            let db = open_policy(&settings);
+            let mut evaluations = vec![];
+            for bound in settings.bounds.iter() {
+                let evaluation =
+                    evaluate(&settings.config(*bound, db.clone(), None), episodes, 2000);
+                println!("{}", evaluation);
+                evaluations.push(evaluation);
+            }
+            if let Some(path) = args.get(1) {
+                let file = File::create(path).expect("could not create json output file");
+                serde_json::to_writer_pretty(file, &evaluations).unwrap();
+            }
         }
-        let db_real = db.clone();
-        handles.push(thread::spawn(move || {
-            let config = Config {
-                bound: i,
-                arena_size: (16, 16),
-                learning: QLearning::new(0.1, 0.01, 2.),
-                db: db_real,
+        "record" => {
+            let episodes = args
+                .get(0)
+                .map(|s| s.parse().expect("episodes must be a number"))
+                .unwrap_or(5);
+            let out_dir = args.get(1).map(|s| s.as_str()).unwrap_or("recordings");
+            let pngs = match args.get(2).map(|s| s.as_str()) {
+                None => false,
+                Some("png") => true,
+                Some(other) => usage_error(other),
             };
-            let mut ai = AiComponents::new(config);
-
-            ai.train();
-        }));
+            let config = settings.config(settings.bounds[0], open_policy(&settings), None);
+            record(config, episodes, 2000, Path::new(out_dir), pngs);
+        }
+        "replay" => {
+            let path = args.get(0).unwrap_or_else(|| usage_error("replay"));
+            let recording = Recording::load(Path::new(path)).unwrap_or_else(fail);
+            let mut replay = Replay::new(recording);
+            let result = {
+                let mut renderer = TerminalRenderer::new().unwrap();
+                replay.play(
+                    &mut renderer,
+                    std::time::Duration::from_millis(settings.tick_ms),
+                )
+            };
+            result.unwrap_or_else(fail);
+        }
+        "db" => match args.get(0).map(|s| s.as_str()) {
+            None => {
+                if settings.policy.is_none() && settings.database.is_dir() {
+                    let store = PolicyStore::new(&settings.database, settings.encoding)
+                        .unwrap_or_else(fail);
+                    for name in store.names().unwrap_or_else(fail) {
+                        print_tables(&store.path(&name).unwrap());
+                    }
+                } else {
+                    print_tables(&settings.database_path().unwrap_or_else(fail));
+                }
+            }
+            Some("convert") => {
+                if args.len() != 3 {
+                    usage_error("db convert");
+                }
+                convert_database(Path::new(&args[1]), Path::new(&args[2])).unwrap_or_else(fail);
+            }
+            Some("merge") => {
+                if args.len() < 4 {
+                    usage_error("db merge");
+                }
+                let policy: MergePolicy = args[1].parse().unwrap_or_else(fail);
+                let inputs: Vec<&Path> = args[3..].iter().map(Path::new).collect();
+                merge_databases(&inputs, Path::new(&args[2]), policy).unwrap_or_else(fail);
+            }
+            Some("stats") => {
+                let db = open_policy(&settings);
+                for bound in stored_bounds(&db) {
+                    let stats = value_stats(&read_table(&db, bound));
+                    println!(
+                        "bound {}: {} states, {} values, min {:.3}, 10% {:.3}, median {:.3}, mean {:.3}, 90% {:.3}, max {:.3}",
+                        bound,
+                        stats.states,
+                        stats.values,
+                        stats.min,
+                        stats.low,
+                        stats.median,
+                        stats.mean,
+                        stats.high,
+                        stats.max
+                    );
+                }
+            }
+            Some(which @ "top") | Some(which @ "bottom") => {
+                let bound = parse_arg(args, 1, "bound");
+                let n = args
+                    .get(2)
+                    .map(|_| parse_arg(args, 2, "count"))
+                    .unwrap_or(10);
+                let db = open_policy(&settings);
+                let table = read_table(&db, bound);
+                let visits = read_visits(&db, bound);
+                for (state, best) in ranked_states(&table, n, which == "top") {
+                    println!(
+                        "map {} features {}: best {}",
+                        state.map, state.features, best
+                    );
+                    println!(
+                        "{}",
+                        render_state(&state, table.get(&state), visits.get(&state))
+                    );
+                }
+            }
+            Some("show") => {
+                let bound = parse_arg(args, 1, "bound");
+                let state = PackedState {
+                    map: parse_arg(args, 2, "map"),
+                    features: parse_arg(args, 3, "features"),
+                };
+                if state.bound() != bound {
+                    fail::<(), _>(format!("that state is for bound {}", state.bound()));
+                }
+                let db = open_policy(&settings);
+                let table = read_table(&db, bound);
+                let visits = read_visits(&db, bound);
+                print!(
+                    "{}",
+                    render_state(&state, table.get(&state), visits.get(&state))
+                );
+            }
+            Some("delete") => {
+                let bound = parse_arg(args, 1, "bound");
+                let db = open_policy(&settings);
+                write_table(&db, bound, None);
+                db.lock().unwrap().save().unwrap_or_else(fail);
+            }
+            Some("prune") => {
+                let db = open_policy(&settings);
+                // the initial value the tables were trained with, if it was
+                // recorded
+                let initial_value = db
+                    .lock()
+                    .unwrap()
+                    .read(|db| db.metadata.learning)
+                    .unwrap()
+                    .unwrap_or(settings.learning)
+                    .initial_value;
+                prune_tables(
+                    &db,
+                    args.get(1).map(|_| parse_arg(args, 1, "bound")),
+                    |table, visits| prune_untouched(table, visits, initial_value),
+                );
+            }
+            Some("prune-rare") => {
+                let min_visits: u64 = parse_arg(args, 1, "minimum visits");
+                let db = open_policy(&settings);
+                prune_tables(
+                    &db,
+                    args.get(2).map(|_| parse_arg(args, 2, "bound")),
+                    |table, visits| prune_rarely_visited(table, visits, min_visits),
+                );
+            }
+            Some(other) => usage_error(other),
+        },
+        _ => usage_error(command),
     }
+}
+
+fn train(settings: &Settings) {
+    let mut handles = vec![];
+    let db = open_policy(&settings);
+    let metrics = settings
+        .metrics
+        .as_ref()
+        .map(|path| MetricsSink::shared(path).unwrap());
+
+    // ctrl-c asks every thread to wrap up, and the database is saved once
+    // they have
+    let stop = Arc::new(AtomicBool::new(false));
     {
-        let local_db = db.clone();
-        handles.push(thread::spawn(move || loop {
-            thread::sleep(std::time::Duration::from_secs(60));
-            let local_db_real = local_db.lock().unwrap();
-            local_db_real.save().unwrap();
-        }));
+        let stop = stop.clone();
+        ctrlc::set_handler(move || stop.store(true, Ordering::SeqCst)).unwrap();
     }
 
+    // AiComponents::new refuses as well, but this reports it rather than
+    // panicking every training thread
+    let config = settings.config(settings.bounds[0], db.clone(), None);
+    db.lock()
+        .unwrap()
+        .read(|db| db.check_state_encoding(&config))
+        .unwrap()
+        .unwrap_or_else(fail);
+
+    let before_apple_fix = db
+        .lock()
+        .unwrap()
+        .read(|db| db.metadata.before_apple_fix.clone())
+        .unwrap();
+    for bound in settings
+        .bounds
+        .iter()
+        .filter(|b| before_apple_fix.contains(b))
     {
-        let config = Config {
-            bound: 3,
-            arena_size: (16, 16),
-            learning: QLearning::new(0.2, 0.1, 2.),
-            db: db.clone(),
-        };
-        test(config);
+        eprintln!(
+            "bound {} was trained before the apple direction was fixed, run db delete {} to \
+             start it over",
+            bound, bound
+        );
     }
 
+    // every worker learns straight into these, and the database only gets a
+    // copy when the saver takes a snapshot
+    let tables = Arc::new(SharedTables::new(db.clone(), DEFAULT_SHARDS));
+
+    for bound in settings.bounds.iter() {
+        for worker in 0..settings.workers_per_bound {
+            let mut config = settings.config(*bound, db.clone(), metrics.clone());
+            config.tables = Some(tables.clone());
+            // workers on the same bound would play the same games otherwise
+            config.seed = config.seed.map(|seed| seed.wrapping_add(worker as u64));
+            let minutes = settings.train_minutes;
+            let stop = stop.clone();
+            handles.push(thread::spawn(move || {
+                let mut ai = AiComponents::new(config);
+                ai.set_stop_flag(stop);
+                ai.train_for_time(minutes);
+            }));
+        }
+    }
+    let saver = {
+        let local_db = db.clone();
+        let tables = tables.clone();
+        let stop = stop.clone();
+        thread::spawn(move || {
+            let mut last_save = std::time::Instant::now();
+            while !stop.load(Ordering::SeqCst) {
+                thread::sleep(std::time::Duration::from_millis(200));
+                if last_save.elapsed().as_secs() >= 60 {
+                    tables.snapshot();
+                    let local_db_real = local_db.lock().unwrap();
+                    local_db_real.save().unwrap();
+                    last_save = std::time::Instant::now();
+                }
+            }
+        })
+    };
+
     for handle in handles {
         handle.join().unwrap();
     }
+    stop.store(true, Ordering::SeqCst);
+    saver.join().unwrap();
 
+    tables.snapshot();
     db.lock().unwrap().save().unwrap();
 }
+
+fn open_policy(settings: &Settings) -> SharedDatabase {
+    return open_database(
+        &settings.database_path().unwrap_or_else(fail),
+        settings.checkpoints,
+    );
+}
+
+// runs prune over the table for bound, or every table if None, and saves
+fn prune_tables<F: Fn(&mut QTable, &mut VisitTable) -> usize>(
+    db: &SharedDatabase,
+    bound: Option<usize>,
+    prune: F,
+) {
+    let bounds = match bound {
+        Some(bound) => vec![bound],
+        None => stored_bounds(db),
+    };
+    for bound in bounds {
+        let mut table = read_table(db, bound);
+        let mut visits = read_visits(db, bound);
+        let removed = prune(&mut table, &mut visits);
+        println!(
+            "bound {}: removed {} values, {} states left",
+            bound,
+            removed,
+            table.len()
+        );
+        write_table(db, bound, Some((table, visits)));
+    }
+    db.lock().unwrap().save().unwrap_or_else(fail);
+}
+
+// every bound with a table in db, sorted
+fn stored_bounds(db: &SharedDatabase) -> Vec<usize> {
+    let mut bounds: Vec<usize> = db
+        .lock()
+        .unwrap()
+        .read(|db| db.tables.keys().cloned().collect())
+        .unwrap();
+    bounds.sort();
+    return bounds;
+}
+
+fn parse_arg<T: std::str::FromStr>(args: &[String], index: usize, name: &str) -> T {
+    let arg = args.get(index).unwrap_or_else(|| usage_error(name));
+    return arg
+        .parse()
+        .unwrap_or_else(|_| fail(format!("{} must be a number, not {}", name, arg)));
+}
+
+fn print_tables(path: &Path) {
+    println!("{}", path.display());
+    let db = read_database(path).unwrap_or_else(fail);
+    let metadata = &db.metadata;
+    println!(
+        "    version {}, created by {} at {}",
+        db.version, metadata.created_with, metadata.created
+    );
+    if let Some(trained_with) = &metadata.trained_with {
+        println!("    last trained by {}", trained_with);
+    }
+    if let Some(arena_size) = metadata.arena_size {
+        println!("    arena size {:?}", arena_size);
+    }
+    if let Some(rewards) = &metadata.rewards {
+        println!("    {:?}", rewards);
+    }
+    if let Some(learning) = &metadata.learning {
+        println!("    {:?}", learning);
+    }
+    if let Some(state_encoding) = &metadata.state_encoding {
+        println!("    {:?}", state_encoding);
+    }
+    let mut bounds: Vec<&usize> = db.tables.keys().collect();
+    bounds.sort();
+    for bound in bounds {
+        print!("    bound {}: {} states", bound, db.tables[bound].len());
+        if metadata.before_apple_fix.contains(bound) {
+            print!(", trained before the apple direction was fixed");
+        }
+        println!();
+    }
+}
+
+fn usage_error(arg: &str) -> ! {
+    if arg.is_empty() {
+        eprintln!("{}", USAGE);
+    } else {
+        eprintln!("unexpected {}\n\n{}", arg, USAGE);
+    }
+    std::process::exit(2);
+}
+
+fn fail<T, E: std::fmt::Display>(e: E) -> T {
+    eprintln!("{}", e);
+    std::process::exit(1);
+}
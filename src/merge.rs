@@ -0,0 +1,354 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::schema::{Envelope, StateEncoding};
+use crate::snake::Action;
+use crate::{PackedState, QTable, VisitTable};
+
+// how to settle a state/action that more than one table has a value for
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MergePolicy {
+    // the value from the table merged last
+    LastWriter,
+    Average,
+    // an average weighted by how often each table visited the state/action
+    Weighted,
+    Max,
+}
+
+impl FromStr for MergePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "last" => Ok(MergePolicy::LastWriter),
+            "average" => Ok(MergePolicy::Average),
+            "weighted" => Ok(MergePolicy::Weighted),
+            "max" => Ok(MergePolicy::Max),
+            _ => Err(format!(
+                "unknown merge policy {}, expected last, average, weighted or max",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum MergeError {
+    // a weighted merge of tables none of which counted their visits
+    NoVisitCounts,
+    // two of the databases key their tables by states built differently
+    StateEncodingsDiffer(StateEncoding, StateEncoding),
+}
+
+impl std::fmt::Display for MergeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            MergeError::NoVisitCounts => write!(
+                f,
+                "none of the databases has visit counts to weight by, use average instead"
+            ),
+            MergeError::StateEncodingsDiffer(a, b) => write!(
+                f,
+                "one database was trained with {:?} and another with {:?}, their tables \
+                 can't be mixed",
+                a, b
+            ),
+        }
+    }
+}
+
+impl std::error::Error for MergeError {}
+
+// copies every value (or visit count) in source into target, replacing what
+// target had
+pub fn overwrite<V>(
+    target: &mut HashMap<PackedState, HashMap<Action, V>>,
+    source: HashMap<PackedState, HashMap<Action, V>>,
+) {
+    for (state, actions) in source {
+        target.entry(state).or_default().extend(actions);
+    }
+}
+
+// combines tables along with their visit counts, earlier ones first. the
+// merged counts are the sums of the counts merged
+pub fn merge_tables(
+    tables: Vec<(QTable, VisitTable)>,
+    policy: MergePolicy,
+) -> (QTable, VisitTable) {
+    let mut merged_visits: VisitTable = HashMap::new();
+    for (_, visits) in tables.iter() {
+        for (state, actions) in visits {
+            let merged_actions = merged_visits.entry(*state).or_default();
+            for (action, count) in actions {
+                *merged_actions.entry(*action).or_insert(0) += count;
+            }
+        }
+    }
+
+    if policy == MergePolicy::LastWriter {
+        let mut merged = HashMap::new();
+        for (table, _) in tables {
+            overwrite(&mut merged, table);
+        }
+        return (merged, merged_visits);
+    }
+
+    // the running max, or the weighted sum and total weight for averages
+    let mut acc: HashMap<PackedState, HashMap<Action, (f64, f64)>> = HashMap::new();
+    for (table, visits) in tables {
+        for (state, actions) in table {
+            let acc_actions = acc.entry(state).or_default();
+            for (action, value) in actions {
+                // values from before visits were counted still count once
+                let weight = match policy {
+                    MergePolicy::Weighted => visits
+                        .get(&state)
+                        .and_then(|m| m.get(&action))
+                        .cloned()
+                        .unwrap_or(0)
+                        .max(1) as f64,
+                    _ => 1.0,
+                };
+                match acc_actions.get_mut(&action) {
+                    Some((sum, total)) if policy == MergePolicy::Max => {
+                        *sum = sum.max(value);
+                        *total += weight;
+                    }
+                    Some((sum, total)) => {
+                        *sum += value * weight;
+                        *total += weight;
+                    }
+                    None if policy == MergePolicy::Max => {
+                        acc_actions.insert(action, (value, weight));
+                    }
+                    None => {
+                        acc_actions.insert(action, (value * weight, weight));
+                    }
+                }
+            }
+        }
+    }
+
+    let merged = acc
+        .into_iter()
+        .map(|(state, actions)| {
+            let actions = actions
+                .into_iter()
+                .map(|(action, (sum, total))| match policy {
+                    MergePolicy::Max => (action, sum),
+                    _ => (action, sum / total),
+                })
+                .collect();
+            (state, actions)
+        })
+        .collect();
+    return (merged, merged_visits);
+}
+
+// merges every bound of every envelope into a new one. its metadata only
+// keeps what every envelope agrees on, and envelopes trained with different
+// state encodings can't be merged at all
+pub fn merge_envelopes(
+    envelopes: Vec<Envelope>,
+    policy: MergePolicy,
+) -> Result<Envelope, MergeError> {
+    let counted = envelopes
+        .iter()
+        .any(|envelope| envelope.visits.values().any(|visits| !visits.is_empty()));
+    if policy == MergePolicy::Weighted && !counted {
+        return Err(MergeError::NoVisitCounts);
+    }
+
+    let mut merged = Envelope::new(HashMap::new());
+    let recorded = envelopes.iter().filter_map(|e| e.metadata.state_encoding);
+    for encoding in recorded {
+        match merged.metadata.state_encoding {
+            Some(first) if first != encoding => {
+                return Err(MergeError::StateEncodingsDiffer(first, encoding));
+            }
+            _ => merged.metadata.state_encoding = Some(encoding),
+        }
+    }
+    let metadata: Vec<_> = envelopes.iter().map(|e| &e.metadata).collect();
+    merged.metadata.trained_with = agreed(metadata.iter().map(|m| m.trained_with.clone()));
+    merged.metadata.arena_size = agreed(metadata.iter().map(|m| m.arena_size));
+    merged.metadata.rewards = agreed(metadata.iter().map(|m| m.rewards));
+    merged.metadata.learning = agreed(metadata.iter().map(|m| m.learning));
+
+    let mut by_bound: HashMap<usize, Vec<(QTable, VisitTable)>> = HashMap::new();
+    let mut before_apple_fix = vec![];
+    for mut envelope in envelopes {
+        before_apple_fix.extend(envelope.metadata.before_apple_fix.drain(..));
+        for (bound, table) in envelope.tables {
+            let visits = envelope.visits.remove(&bound).unwrap_or_default();
+            by_bound.entry(bound).or_default().push((table, visits));
+        }
+    }
+    for (bound, tables) in by_bound {
+        let (table, visits) = merge_tables(tables, policy);
+        merged.tables.insert(bound, table);
+        merged.visits.insert(bound, visits);
+    }
+    // a bound is only as trustworthy as the worst table merged into it
+    before_apple_fix.sort();
+    before_apple_fix.dedup();
+    merged.metadata.before_apple_fix = before_apple_fix;
+    return Ok(merged);
+}
+
+// the value every one of values has, None if they differ or one is missing
+fn agreed<T: PartialEq>(mut values: impl Iterator<Item = Option<T>>) -> Option<T> {
+    let first = values.next()??;
+    for value in values {
+        if value.as_ref() != Some(&first) {
+            return None;
+        }
+    }
+    return Some(first);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ActionMode, AppleFeatures, MyState};
+
+    fn state() -> PackedState {
+        return PackedState::from(&MyState::new(3));
+    }
+
+    fn table(value: f64, count: Option<u64>) -> (QTable, VisitTable) {
+        let mut table = HashMap::new();
+        table.insert(state(), vec![(Action::XPos, value)].into_iter().collect());
+        let mut visits = HashMap::new();
+        if let Some(count) = count {
+            visits.insert(state(), vec![(Action::XPos, count)].into_iter().collect());
+        }
+        return (table, visits);
+    }
+
+    fn merged(tables: Vec<(QTable, VisitTable)>, policy: MergePolicy) -> (f64, u64) {
+        let (table, visits) = merge_tables(tables, policy);
+        let count = visits
+            .get(&state())
+            .and_then(|m| m.get(&Action::XPos))
+            .cloned()
+            .unwrap_or(0);
+        return (table[&state()][&Action::XPos], count);
+    }
+
+    fn envelope(bound: usize, value: f64, symmetry: Option<bool>) -> Envelope {
+        let mut envelope = Envelope::new(HashMap::new());
+        let (table, visits) = table(value, Some(1));
+        envelope.tables.insert(bound, table);
+        envelope.visits.insert(bound, visits);
+        envelope.metadata.state_encoding = symmetry.map(|symmetry| StateEncoding {
+            apple_features: AppleFeatures::default(),
+            action_mode: ActionMode::Absolute,
+            symmetry: symmetry,
+        });
+        return envelope;
+    }
+
+    #[test]
+    fn parses_every_policy() {
+        assert_eq!("last".parse(), Ok(MergePolicy::LastWriter));
+        assert_eq!("average".parse(), Ok(MergePolicy::Average));
+        assert_eq!("weighted".parse(), Ok(MergePolicy::Weighted));
+        assert_eq!("max".parse(), Ok(MergePolicy::Max));
+        assert!("mean".parse::<MergePolicy>().is_err());
+    }
+
+    #[test]
+    fn last_writer_keeps_the_last_value() {
+        let tables = vec![table(1.0, Some(3)), table(4.0, Some(1))];
+        assert_eq!(merged(tables, MergePolicy::LastWriter), (4.0, 4));
+    }
+
+    #[test]
+    fn average_ignores_visits() {
+        let tables = vec![table(1.0, Some(3)), table(4.0, Some(1))];
+        assert_eq!(merged(tables, MergePolicy::Average), (2.5, 4));
+    }
+
+    #[test]
+    fn weighted_follows_visits() {
+        let tables = vec![table(1.0, Some(3)), table(5.0, Some(1))];
+        assert_eq!(merged(tables, MergePolicy::Weighted), (2.0, 4));
+        // values with no count count once
+        let tables = vec![table(1.0, Some(3)), table(5.0, None)];
+        assert_eq!(merged(tables, MergePolicy::Weighted), (2.0, 3));
+    }
+
+    #[test]
+    fn weighted_needs_visit_counts() {
+        let envelopes = vec![Envelope::new(HashMap::new()), Envelope::new(HashMap::new())];
+        match merge_envelopes(envelopes, MergePolicy::Weighted) {
+            Err(MergeError::NoVisitCounts) => (),
+            other => panic!("expected NoVisitCounts, got {:?}", other.map(|_| ())),
+        }
+    }
+
+    #[test]
+    fn max_keeps_the_highest_value() {
+        let tables = vec![table(1.0, Some(3)), table(-4.0, Some(1))];
+        assert_eq!(merged(tables, MergePolicy::Max), (1.0, 4));
+    }
+
+    #[test]
+    fn states_in_one_table_are_kept() {
+        let (mut only, _) = table(1.0, None);
+        let other = PackedState::from(&MyState {
+            curr_apple: (1, 0),
+            ..MyState::new(3)
+        });
+        only.insert(other, vec![(Action::YPos, 2.0)].into_iter().collect());
+        let tables = vec![(only, HashMap::new()), table(3.0, None)];
+        let (merged, _) = merge_tables(tables, MergePolicy::Average);
+        assert_eq!(merged[&other][&Action::YPos], 2.0);
+        assert_eq!(merged[&state()][&Action::XPos], 2.0);
+    }
+
+    #[test]
+    fn envelopes_merge_by_bound() {
+        let mut first = envelope(3, 1.0, Some(false));
+        first.metadata.before_apple_fix = vec![3];
+        let second = envelope(5, 6.0, None);
+
+        let merged = merge_envelopes(vec![first, second], MergePolicy::Average).unwrap();
+        assert_eq!(merged.tables[&3][&state()][&Action::XPos], 1.0);
+        assert_eq!(merged.tables[&5][&state()][&Action::XPos], 6.0);
+        assert_eq!(merged.visits[&3][&state()][&Action::XPos], 1);
+        assert_eq!(merged.metadata.state_encoding.unwrap().symmetry, false);
+        assert_eq!(merged.metadata.before_apple_fix, vec![3]);
+    }
+
+    #[test]
+    fn keeps_only_the_metadata_every_input_agrees_on() {
+        let mut first = envelope(3, 1.0, None);
+        let mut second = envelope(3, 2.0, None);
+        for envelope in [&mut first, &mut second].iter_mut() {
+            envelope.metadata.arena_size = Some((8, 8));
+        }
+        first.metadata.learning = Some(crate::QLearning::new(0.1, 0.5, 2.0));
+        second.metadata.learning = Some(crate::QLearning::new(0.2, 0.5, 2.0));
+        let merged = merge_envelopes(vec![first, second], MergePolicy::Average).unwrap();
+        assert_eq!(merged.metadata.arena_size, Some((8, 8)));
+        assert_eq!(merged.metadata.learning, None);
+        assert_eq!(merged.metadata.rewards, None);
+    }
+
+    #[test]
+    fn refuses_different_state_encodings() {
+        let envelopes = vec![
+            envelope(3, 1.0, Some(false)),
+            envelope(3, 2.0, None),
+            envelope(3, 3.0, Some(true)),
+        ];
+        match merge_envelopes(envelopes, MergePolicy::Average) {
+            Err(MergeError::StateEncodingsDiffer(a, b)) => assert_ne!(a, b),
+            other => panic!("expected StateEncodingsDiffer, got {:?}", other.map(|_| ())),
+        }
+    }
+}
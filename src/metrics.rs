@@ -0,0 +1,95 @@
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+// what happened during one training window (one pass of the loop in
+// AiComponents::train_for_time) for one bound
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WindowMetrics {
+    pub bound: usize,
+    // counts up from 0 for each AiComponents
+    pub window: u32,
+    pub episodes: u32,
+    // length of the snake when each episode ended
+    pub average_length: f64,
+    // states in the table at the end of the window, and how many of them
+    // this worker was the first to reach
+    pub table_size: usize,
+    pub new_states: usize,
+    pub wall_time_secs: f64,
+}
+
+const CSV_HEADER: &str =
+    "bound,window,episodes,average_length,table_size,new_states,wall_time_secs";
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum MetricsFormat {
+    Csv,
+    JsonLines,
+}
+
+impl MetricsFormat {
+    // .csv files are csv, anything else gets one json object per line
+    pub fn from_path(path: &Path) -> MetricsFormat {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("csv") => MetricsFormat::Csv,
+            _ => MetricsFormat::JsonLines,
+        }
+    }
+}
+
+// appends a row per training window to a file, flushing as it goes so the
+// file can be plotted while training is still running
+pub struct MetricsSink {
+    writer: BufWriter<File>,
+    format: MetricsFormat,
+}
+
+// shared between the training threads of every bound
+pub type SharedMetrics = Arc<Mutex<MetricsSink>>;
+
+impl MetricsSink {
+    pub fn open(path: &Path) -> std::io::Result<MetricsSink> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let empty = file.metadata()?.len() == 0;
+        let format = MetricsFormat::from_path(path);
+
+        let mut writer = BufWriter::new(file);
+        if empty && format == MetricsFormat::Csv {
+            writeln!(writer, "{}", CSV_HEADER)?;
+            writer.flush()?;
+        }
+        return Ok(MetricsSink {
+            writer: writer,
+            format: format,
+        });
+    }
+
+    pub fn shared(path: &Path) -> std::io::Result<SharedMetrics> {
+        return Ok(Arc::new(Mutex::new(MetricsSink::open(path)?)));
+    }
+
+    pub fn write(&mut self, metrics: &WindowMetrics) -> std::io::Result<()> {
+        match self.format {
+            MetricsFormat::Csv => writeln!(
+                self.writer,
+                "{},{},{},{},{},{},{}",
+                metrics.bound,
+                metrics.window,
+                metrics.episodes,
+                metrics.average_length,
+                metrics.table_size,
+                metrics.new_states,
+                metrics.wall_time_secs
+            )?,
+            MetricsFormat::JsonLines => {
+                serde_json::to_writer(&mut self.writer, metrics)?;
+                writeln!(self.writer)?;
+            }
+        }
+        return self.writer.flush();
+    }
+}
@@ -0,0 +1,143 @@
+use serde::{Deserialize, Serialize};
+
+use crate::snake::Action;
+use crate::{MapState, MyState};
+
+// the same information as MyState squeezed into two integers, so keys are
+// cheap to hash, clone and store. windows up to 11x11 fit in map
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PackedState {
+    // bit i * bound + j is set when map[i][j] is Death
+    pub map: u128,
+    // bits 0-3 bound, 4-5 apple x direction + 1, 6-7 apple y direction + 1,
+    // 8 apple in window, 9 relative, 10 and up apple distance
+    pub features: u32,
+}
+
+pub const MAX_BOUND: usize = 11;
+
+impl PackedState {
+    pub fn bound(&self) -> usize {
+        return (self.features & 0b1111) as usize;
+    }
+
+    pub fn relative(&self) -> bool {
+        return self.features & (1 << 9) != 0;
+    }
+
+    pub fn actions(&self) -> Vec<Action> {
+        if self.relative() {
+            return vec![Action::TurnLeft, Action::Straight, Action::TurnRight];
+        }
+        vec![Action::XPos, Action::YPos, Action::XNeg, Action::YNeg]
+    }
+}
+
+impl From<&MyState> for PackedState {
+    fn from(state: &MyState) -> Self {
+        let bound = state.map.len();
+        if bound > MAX_BOUND {
+            panic!("bound {} does not fit in a PackedState", bound);
+        }
+
+        let mut map = 0u128;
+        for i in 0..bound {
+            for j in 0..bound {
+                if state.map[i][j] == MapState::Death {
+                    map |= 1 << (i * bound + j);
+                }
+            }
+        }
+
+        let mut features = bound as u32;
+        features |= ((state.curr_apple.0 + 1) as u32) << 4;
+        features |= ((state.curr_apple.1 + 1) as u32) << 6;
+        features |= (state.apple_in_window as u32) << 8;
+        features |= (state.relative as u32) << 9;
+        features |= (state.apple_distance as u32) << 10;
+
+        return PackedState {
+            map: map,
+            features: features,
+        };
+    }
+}
+
+impl From<&PackedState> for MyState {
+    fn from(packed: &PackedState) -> Self {
+        let bound = packed.bound();
+        let mut state = MyState::new(bound);
+        for i in 0..bound {
+            for j in 0..bound {
+                if packed.map & (1 << (i * bound + j)) != 0 {
+                    state.map[i][j] = MapState::Death;
+                }
+            }
+        }
+        state.curr_apple = (
+            ((packed.features >> 4) & 0b11) as i32 - 1,
+            ((packed.features >> 6) & 0b11) as i32 - 1,
+        );
+        state.apple_in_window = packed.features & (1 << 8) != 0;
+        state.relative = packed.relative();
+        state.apple_distance = (packed.features >> 10) as i32;
+        return state;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(bound: usize) -> MyState {
+        let mut state = MyState::new(bound);
+        state.map[0][bound - 1] = MapState::Death;
+        state.map[bound - 1][0] = MapState::Death;
+        state.map[bound / 2][bound / 2 + 1] = MapState::Death;
+        return state;
+    }
+
+    #[test]
+    fn round_trips_every_bound() {
+        for bound in (3..=MAX_BOUND).step_by(2) {
+            let state = state(bound);
+            let packed = PackedState::from(&state);
+            assert_eq!(packed.bound(), bound);
+            assert_eq!(MyState::from(&packed), state);
+        }
+    }
+
+    #[test]
+    fn round_trips_apple_features() {
+        for x in -1..=1 {
+            for y in -1..=1 {
+                let mut state = state(5);
+                state.curr_apple = (x, y);
+                state.apple_distance = 3;
+                state.apple_in_window = true;
+                state.relative = true;
+                let packed = PackedState::from(&state);
+                assert!(packed.relative());
+                assert_eq!(MyState::from(&packed), state);
+            }
+        }
+    }
+
+    #[test]
+    fn relative_states_have_relative_actions() {
+        let mut state = state(3);
+        assert_eq!(PackedState::from(&state).actions().len(), 4);
+        state.relative = true;
+        assert_eq!(
+            PackedState::from(&state).actions(),
+            vec![Action::TurnLeft, Action::Straight, Action::TurnRight]
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn refuses_bounds_that_do_not_fit() {
+        let _ = PackedState::from(&MyState::new(MAX_BOUND + 2));
+    }
+}
@@ -0,0 +1,72 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::{open_database, Encoding, SharedDatabase};
+
+// a directory of databases, one per named policy, so that experiments running
+// side by side each learn into their own file instead of overwriting each other
+pub struct PolicyStore {
+    dir: PathBuf,
+    // what policies that don't have a file yet get written as
+    encoding: Encoding,
+}
+
+impl PolicyStore {
+    // creates dir if it isn't there yet
+    pub fn new(dir: &Path, encoding: Encoding) -> io::Result<PolicyStore> {
+        std::fs::create_dir_all(dir)?;
+        return Ok(PolicyStore {
+            dir: dir.to_path_buf(),
+            encoding: encoding,
+        });
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    // the existing file for name in either encoding, otherwise where a new
+    // one would go. names become file names, so they can't reach outside the
+    // directory
+    pub fn path(&self, name: &str) -> io::Result<PathBuf> {
+        if name.is_empty() || name.starts_with('.') || name.contains(|c| c == '/' || c == '\\') {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("{:?} is not a valid policy name", name),
+            ));
+        }
+        for encoding in [Encoding::Ron, Encoding::Bincode].iter() {
+            let path = self.dir.join(format!("{}.{}", name, encoding.extension()));
+            if path.exists() {
+                return Ok(path);
+            }
+        }
+        return Ok(self
+            .dir
+            .join(format!("{}.{}", name, self.encoding.extension())));
+    }
+
+    pub fn open(&self, name: &str, checkpoints: usize) -> io::Result<SharedDatabase> {
+        return Ok(open_database(&self.path(name)?, checkpoints));
+    }
+
+    // every policy with a database in the directory, sorted
+    pub fn names(&self) -> io::Result<Vec<String>> {
+        let mut names = vec![];
+        for entry in std::fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            match path.extension().and_then(|ext| ext.to_str()) {
+                Some("ron") | Some("bin") => {}
+                _ => continue,
+            }
+            // checkpoints are kept in a directory of their own, so every
+            // file here is a policy
+            if let Some(name) = path.file_stem().and_then(|stem| stem.to_str()) {
+                names.push(name.to_string());
+            }
+        }
+        names.sort();
+        names.dedup();
+        return Ok(names);
+    }
+}
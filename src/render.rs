@@ -0,0 +1,152 @@
+use glium::{glutin, Surface};
+use rust_lm::Mat4;
+
+use crate::snake::Arena;
+
+// something that shows the game; the arena never calls into it, so callers
+// decide when (and whether) a frame gets drawn
+pub trait Renderer {
+    fn render(&mut self, arena: &Arena);
+}
+
+#[derive(Copy, Clone)]
+struct Vertex {
+    position: [f32; 2],
+    vec_color: (f32, f32, f32),
+}
+
+implement_vertex!(Vertex, position, vec_color);
+
+pub struct GliumRenderer {
+    display: glium::Display,
+    program: glium::Program,
+    transform_matrix: Mat4,
+}
+
+impl GliumRenderer {
+    pub fn new(
+        arena_size: (i32, i32),
+        events_loop: &glutin::event_loop::EventLoop<()>,
+    ) -> GliumRenderer {
+        let wb = glium::glutin::window::WindowBuilder::new()
+            .with_inner_size(glium::glutin::dpi::LogicalSize::new(640.0, 640.0))
+            .with_title("snake");
+        let cb = glium::glutin::ContextBuilder::new().with_vsync(true);
+        let display = glium::Display::new(wb, cb, events_loop).unwrap();
+
+        let vertex_shader_src = r#"
+        #version 140
+        in vec2 position;
+        in vec3 vec_color;
+        out vec3 my_color;
+        uniform mat4 matrix;
+        void main() {
+            my_color = vec_color;
+            gl_Position = matrix * vec4(position, 0.0, 1.0);
+        }
+    "#;
+
+        let fragment_shader_src = r#"
+        #version 140
+        in vec3 my_color;
+        out vec4 color;
+        void main() {
+            color = vec4(my_color, 1.0);
+        }
+    "#;
+
+        let program =
+            glium::Program::from_source(&display, vertex_shader_src, fragment_shader_src, None)
+                .unwrap();
+
+        let transform_matrix = Mat4::identity()
+            .scale_by(
+                2.0 / (arena_size.0 as f32),
+                2.0 / (arena_size.1 as f32),
+                1.0,
+            )
+            .translate_by(-1.0, -1.0, 0.0);
+
+        return GliumRenderer {
+            display: display,
+            program: program,
+            transform_matrix: transform_matrix,
+        };
+    }
+}
+
+impl Renderer for GliumRenderer {
+    fn render(&mut self, arena: &Arena) {
+        let mut target = self.display.draw();
+
+        target.clear_color(0.0, 0.0, 0.0, 1.0);
+
+        let side_length = 1.0f32;
+        let mut points: Vec<[f32; 2]> = Vec::new();
+
+        for thing in arena.snake.iter() {
+            points.push([thing.0 as f32, thing.1 as f32]);
+            points.push([thing.0 as f32, thing.1 as f32 + side_length]);
+            points.push([thing.0 as f32 + side_length, thing.1 as f32 + side_length]);
+
+            points.push([thing.0 as f32 + side_length, thing.1 as f32 + side_length]);
+            points.push([thing.0 as f32 + side_length, thing.1 as f32]);
+            points.push([thing.0 as f32, thing.1 as f32]);
+        }
+        let mut points_proper = points_to_points_proper(points, (0.0, 0.5, 0.0));
+
+        if arena.apple_pos.2 {
+            let mut points: Vec<[f32; 2]> = Vec::new();
+            points.push([arena.apple_pos.0 as f32, arena.apple_pos.1 as f32]);
+            points.push([
+                arena.apple_pos.0 as f32,
+                arena.apple_pos.1 as f32 + side_length,
+            ]);
+            points.push([
+                arena.apple_pos.0 as f32 + side_length,
+                arena.apple_pos.1 as f32 + side_length,
+            ]);
+
+            points.push([
+                arena.apple_pos.0 as f32 + side_length,
+                arena.apple_pos.1 as f32 + side_length,
+            ]);
+            points.push([
+                arena.apple_pos.0 as f32 + side_length,
+                arena.apple_pos.1 as f32,
+            ]);
+            points.push([arena.apple_pos.0 as f32, arena.apple_pos.1 as f32]);
+
+            points_proper.append(&mut points_to_points_proper(points, (1.0, 0.0, 0.0)));
+        }
+
+        let uniforms = uniform! {
+            matrix: self.transform_matrix.matrix,
+        };
+
+        let vertex_buffer = glium::VertexBuffer::new(&self.display, &points_proper).unwrap();
+        let index_buffer = glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
+        target
+            .draw(
+                &vertex_buffer,
+                &index_buffer,
+                &self.program,
+                &uniforms,
+                &Default::default(),
+            )
+            .unwrap();
+
+        target.finish().unwrap();
+    }
+}
+
+fn points_to_points_proper(points: Vec<[f32; 2]>, color: (f32, f32, f32)) -> Vec<Vertex> {
+    let mut points_proper: Vec<Vertex> = Vec::new();
+    for point in points {
+        points_proper.push(Vertex {
+            position: point,
+            vec_color: color,
+        });
+    }
+    return points_proper;
+}
@@ -0,0 +1,391 @@
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+
+use crate::render::Renderer;
+use crate::snake::{Action, Arena, DeathCause, TickOutcome};
+use crate::{RewardConfig, StarvationLimit};
+
+// bump this whenever Recording or TickRecord change shape
+pub const RECORDING_VERSION: u32 = 2;
+
+// everything needed to re-simulate a run: the arena is rebuilt from seed,
+// arena_size, bound, rewards and starvation, then fed the recorded actions
+// one by one. the rest of each TickRecord is what happened at the time, kept
+// to check the replay against and to read without replaying at all
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Recording {
+    pub version: u32,
+    pub seed: u64,
+    pub arena_size: (i32, i32),
+    pub bound: usize,
+    pub rewards: RewardConfig,
+    pub starvation: Option<StarvationLimit>,
+    pub ticks: Vec<TickRecord>,
+}
+
+// version 1, made with the default rewards and no starvation limit
+#[derive(Deserialize)]
+#[cfg_attr(test, derive(Serialize))]
+struct RecordingV1 {
+    version: u32,
+    seed: u64,
+    arena_size: (i32, i32),
+    bound: usize,
+    ticks: Vec<TickRecord>,
+}
+
+impl From<RecordingV1> for Recording {
+    fn from(old: RecordingV1) -> Self {
+        return Recording {
+            version: old.version,
+            seed: old.seed,
+            arena_size: old.arena_size,
+            bound: old.bound,
+            rewards: RewardConfig::default(),
+            starvation: None,
+            ticks: old.ticks,
+        };
+    }
+}
+
+// the first field of every version, read to pick the layout to decode
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct TickRecord {
+    pub action: Action,
+    pub reward: f64,
+    // where a new apple appeared during this tick, if one did
+    pub apple_spawn: Option<(i32, i32)>,
+    pub death: Option<DeathCause>,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Format {
+    Ron,
+    Binary,
+}
+
+impl Format {
+    // .ron files are text, anything else is treated as binary
+    pub fn from_path(path: &Path) -> Format {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("ron") => Format::Ron,
+            _ => Format::Binary,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RecordingError {
+    Io(std::io::Error),
+    Ron(ron::Error),
+    Binary(bincode::Error),
+    UnsupportedVersion(u32),
+    // the re-simulated tick at this index didn't match the recorded one
+    Diverged(usize),
+}
+
+impl std::fmt::Display for RecordingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RecordingError::Io(e) => write!(f, "io error: {}", e),
+            RecordingError::Ron(e) => write!(f, "ron error: {}", e),
+            RecordingError::Binary(e) => write!(f, "binary error: {}", e),
+            RecordingError::UnsupportedVersion(v) => write!(
+                f,
+                "recording version {} is newer than supported version {}",
+                v, RECORDING_VERSION
+            ),
+            RecordingError::Diverged(i) => write!(f, "replay diverged at tick {}", i),
+        }
+    }
+}
+
+impl std::error::Error for RecordingError {}
+
+impl From<std::io::Error> for RecordingError {
+    fn from(e: std::io::Error) -> Self {
+        RecordingError::Io(e)
+    }
+}
+
+impl From<ron::Error> for RecordingError {
+    fn from(e: ron::Error) -> Self {
+        RecordingError::Ron(e)
+    }
+}
+
+impl From<bincode::Error> for RecordingError {
+    fn from(e: bincode::Error) -> Self {
+        RecordingError::Binary(e)
+    }
+}
+
+impl Recording {
+    pub fn save(&self, path: &Path, format: Format) -> Result<(), RecordingError> {
+        let writer = BufWriter::new(File::create(path)?);
+        match format {
+            Format::Ron => ron::ser::to_writer_pretty(writer, self, Default::default())?,
+            Format::Binary => bincode::serialize_into(writer, self)?,
+        }
+        return Ok(());
+    }
+
+    pub fn load(path: &Path) -> Result<Recording, RecordingError> {
+        let bytes = std::fs::read(path)?;
+        let format = Format::from_path(path);
+        let version = decode::<VersionOnly>(&bytes, format)?.version;
+        if version > RECORDING_VERSION {
+            return Err(RecordingError::UnsupportedVersion(version));
+        }
+        // binary recordings have no field names, so older ones can't be
+        // filled in with defaults and need their own layout
+        if version == 1 {
+            return Ok(Recording::from(decode::<RecordingV1>(&bytes, format)?));
+        }
+        return decode(&bytes, format);
+    }
+}
+
+fn decode<T: DeserializeOwned>(bytes: &[u8], format: Format) -> Result<T, RecordingError> {
+    match format {
+        Format::Ron => Ok(ron::de::from_bytes(bytes)?),
+        Format::Binary => Ok(bincode::deserialize(bytes)?),
+    }
+}
+
+// ticks an arena on the caller's behalf and writes down what happened
+pub struct Recorder {
+    recording: Recording,
+}
+
+impl Recorder {
+    // reseeds the arena so that the recording starts from a state that can be rebuilt
+    pub fn start(arena: &mut Arena, seed: u64) -> Recorder {
+        arena.reseed(seed);
+        return Recorder {
+            recording: Recording {
+                version: RECORDING_VERSION,
+                seed: seed,
+                arena_size: arena.arena_size,
+                bound: arena.bound(),
+                rewards: arena.rewards(),
+                starvation: arena.starvation(),
+                ticks: Vec::new(),
+            },
+        };
+    }
+
+    // same as arena.tick(action), except that a game that ends is reset
+    // straight away so the recording can carry on into the next one
+    pub fn tick(&mut self, arena: &mut Arena, action: Action) -> TickOutcome {
+        let (record, outcome) = tick_and_describe(arena, action);
+        self.recording.ticks.push(record);
+        return outcome;
+    }
+
+    pub fn finish(self) -> Recording {
+        return self.recording;
+    }
+}
+
+fn tick_and_describe(arena: &mut Arena, action: Action) -> (TickRecord, TickOutcome) {
+    let outcome = arena.tick(action);
+    let reward = arena.reward_for_last_action;
+    let death = arena.last_death;
+    if arena.finished() {
+        arena.reset();
+    }
+    // a new apple appears when the old one is eaten or the game restarts
+    let apple_spawn = match outcome {
+        TickOutcome::Moved => None,
+        TickOutcome::Ate | TickOutcome::Died { .. } => Some((arena.apple_pos.0, arena.apple_pos.1)),
+    };
+    let record = TickRecord {
+        action: action,
+        reward: reward,
+        apple_spawn: apple_spawn,
+        death: death,
+    };
+    return (record, outcome);
+}
+
+// re-simulates a recording through Arena::tick
+pub struct Replay {
+    recording: Recording,
+    arena: Arena,
+    position: usize,
+}
+
+impl Replay {
+    pub fn new(recording: Recording) -> Replay {
+        let mut arena = Arena::new(recording.arena_size, recording.bound, None);
+        arena.set_rewards(recording.rewards);
+        arena.set_starvation(recording.starvation);
+        arena.reseed(recording.seed);
+        return Replay {
+            recording: recording,
+            arena: arena,
+            position: 0,
+        };
+    }
+
+    pub fn arena(&self) -> &Arena {
+        return &self.arena;
+    }
+
+    // index of the next tick to be replayed
+    pub fn position(&self) -> usize {
+        return self.position;
+    }
+
+    // replays one tick and returns what happened, None once the recording is
+    // exhausted. an error means the game no longer plays out the way it did
+    // when it was recorded
+    pub fn step(&mut self) -> Result<Option<TickRecord>, RecordingError> {
+        let expected = match self.recording.ticks.get(self.position) {
+            Some(expected) => expected.clone(),
+            None => return Ok(None),
+        };
+        let (actual, _) = tick_and_describe(&mut self.arena, expected.action);
+        if actual != expected {
+            return Err(RecordingError::Diverged(self.position));
+        }
+        self.position += 1;
+        return Ok(Some(actual));
+    }
+
+    // replays the rest of the recording, drawing every tick_rate
+    pub fn play(
+        &mut self,
+        renderer: &mut dyn Renderer,
+        tick_rate: Duration,
+    ) -> Result<(), RecordingError> {
+        renderer.render(&self.arena);
+        while let Some(_) = self.step()? {
+            std::thread::sleep(tick_rate);
+            renderer.render(&self.arena);
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const ACTIONS: [Action; 4] = [Action::XPos, Action::YPos, Action::XNeg, Action::YNeg];
+
+    fn temp_path(name: &str) -> PathBuf {
+        return std::env::temp_dir().join(format!("snake_ai_{}_{}", std::process::id(), name));
+    }
+
+    // long enough to eat, die and start over a few times
+    fn record(starvation: Option<StarvationLimit>) -> Recording {
+        let mut arena = Arena::new((8, 8), 3, None);
+        arena.set_starvation(starvation);
+        let mut recorder = Recorder::start(&mut arena, 7);
+        for i in 0..200 {
+            recorder.tick(&mut arena, ACTIONS[(i * 7 / 5) % 4]);
+        }
+        return recorder.finish();
+    }
+
+    fn replays_cleanly(recording: Recording) {
+        let ticks = recording.ticks.clone();
+        let mut replay = Replay::new(recording);
+        for expected in ticks.iter() {
+            assert_eq!(replay.step().unwrap().as_ref(), Some(expected));
+        }
+        assert_eq!(replay.step().unwrap(), None);
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let recording = record(Some(StarvationLimit {
+            steps: 10,
+            per_area: 0.0,
+            per_length: 0.0,
+        }));
+        assert!(recording.ticks.iter().any(|tick| tick.death.is_some()));
+        replays_cleanly(recording);
+    }
+
+    #[test]
+    fn notices_a_different_game() {
+        let mut recording = record(None);
+        recording.seed += 1;
+        let mut replay = Replay::new(recording);
+        loop {
+            match replay.step() {
+                Ok(Some(_)) => (),
+                Ok(None) => panic!("replay with the wrong seed did not diverge"),
+                Err(RecordingError::Diverged(_)) => break,
+                Err(e) => panic!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn round_trips_both_formats() {
+        for (name, format) in [("trip.ron", Format::Ron), ("trip.bin", Format::Binary)].iter() {
+            let path = temp_path(name);
+            let recording = record(None);
+            recording.save(&path, *format).unwrap();
+            let loaded = Recording::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.version, RECORDING_VERSION);
+            assert_eq!(loaded.ticks, recording.ticks);
+            replays_cleanly(loaded);
+        }
+    }
+
+    #[test]
+    fn reads_version_1() {
+        let recording = record(None);
+        let old = RecordingV1 {
+            version: 1,
+            seed: recording.seed,
+            arena_size: recording.arena_size,
+            bound: recording.bound,
+            ticks: recording.ticks.clone(),
+        };
+        for name in ["old.ron", "old.bin"].iter() {
+            let path = temp_path(name);
+            let bytes = match Format::from_path(&path) {
+                Format::Ron => ron::to_string(&old).unwrap().into_bytes(),
+                Format::Binary => bincode::serialize(&old).unwrap(),
+            };
+            std::fs::write(&path, bytes).unwrap();
+            let loaded = Recording::load(&path).unwrap();
+            std::fs::remove_file(&path).unwrap();
+            assert_eq!(loaded.version, 1);
+            assert_eq!(loaded.starvation, None);
+            replays_cleanly(loaded);
+        }
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        let mut recording = record(None);
+        recording.version = RECORDING_VERSION + 1;
+        let path = temp_path("new.bin");
+        recording.save(&path, Format::Binary).unwrap();
+        let loaded = Recording::load(&path);
+        std::fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(RecordingError::UnsupportedVersion(v)) => assert_eq!(v, RECORDING_VERSION + 1),
+            _ => panic!("expected UnsupportedVersion"),
+        }
+    }
+}
@@ -0,0 +1,289 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    legacy, ActionMode, AppleFeatures, Config, Encoding, QLearning, QTable, RewardConfig,
+    VisitTable,
+};
+
+// bump this and add a step to migrate() whenever Envelope or anything in a
+// QTable changes shape.
+//   0: a bare HashMap<usize, QTable> keyed by MyState with the reward inside
+//      the key, as the tables were first written
+//   1: Envelope
+pub const SCHEMA_VERSION: u32 = 1;
+
+// what is written to disk: the tables (one per bound) along with what
+// produced them
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Envelope {
+    // kept first so it can be read on its own
+    pub version: u32,
+    pub metadata: Metadata,
+    pub tables: HashMap<usize, QTable>,
+    // how many times training updated each value in tables, by bound
+    pub visits: HashMap<usize, VisitTable>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Metadata {
+    // version of this crate that created the database, and when (seconds
+    // since the unix epoch)
+    pub created_with: String,
+    pub created: u64,
+    // the settings of the most recent training run, None until something
+    // has trained into the database
+    pub trained_with: Option<String>,
+    pub arena_size: Option<(i32, i32)>,
+    pub rewards: Option<RewardConfig>,
+    pub learning: Option<QLearning>,
+    // what the keys in tables mean, None until something has trained into
+    // the database. tables trained with different settings can't be mixed
+    pub state_encoding: Option<StateEncoding>,
+    // bounds migrated from version 0, whose tables were trained before the
+    // apple direction was fixed and read its y component from the x offset.
+    // they stay until deleted, the user decides whether to retrain
+    pub before_apple_fix: Vec<usize>,
+}
+
+// the settings that decide how the game is turned into a PackedState
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StateEncoding {
+    pub apple_features: AppleFeatures,
+    pub action_mode: ActionMode,
+    pub symmetry: bool,
+}
+
+impl StateEncoding {
+    pub fn from_config(config: &Config) -> StateEncoding {
+        return StateEncoding {
+            apple_features: config.apple_features,
+            action_mode: config.action_mode,
+            symmetry: config.symmetry,
+        };
+    }
+}
+
+impl Envelope {
+    pub fn new(tables: HashMap<usize, QTable>) -> Envelope {
+        return Envelope {
+            version: SCHEMA_VERSION,
+            metadata: Metadata {
+                created_with: env!("CARGO_PKG_VERSION").to_string(),
+                created: SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs())
+                    .unwrap_or(0),
+                trained_with: None,
+                arena_size: None,
+                rewards: None,
+                learning: None,
+                state_encoding: None,
+                before_apple_fix: Vec::new(),
+            },
+            tables: tables,
+            visits: HashMap::new(),
+        };
+    }
+
+    pub fn record_training(&mut self, config: &Config) {
+        self.metadata.trained_with = Some(env!("CARGO_PKG_VERSION").to_string());
+        self.metadata.arena_size = Some(config.arena_size);
+        self.metadata.rewards = Some(config.rewards);
+        self.metadata.learning = Some(config.learning);
+        self.metadata.state_encoding = Some(StateEncoding::from_config(config));
+    }
+
+    // whether config turns the game into the same keys the tables were
+    // trained with, always fine if that was never recorded
+    pub fn check_state_encoding(&self, config: &Config) -> Result<(), SchemaError> {
+        let wanted = StateEncoding::from_config(config);
+        match self.metadata.state_encoding {
+            Some(stored) if stored != wanted => Err(SchemaError::StateEncodingChanged {
+                stored: stored,
+                wanted: wanted,
+            }),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Envelope::new(HashMap::new())
+    }
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    // written by a newer version of this crate
+    TooNew(u32),
+    // not any layout this crate has ever written
+    Unreadable,
+    // the tables are keyed by states built with other settings
+    StateEncodingChanged {
+        stored: StateEncoding,
+        wanted: StateEncoding,
+    },
+}
+
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SchemaError::TooNew(v) => write!(
+                f,
+                "database version {} is newer than supported version {}",
+                v, SCHEMA_VERSION
+            ),
+            SchemaError::Unreadable => write!(f, "database is not in any known layout"),
+            SchemaError::StateEncodingChanged { stored, wanted } => write!(
+                f,
+                "database was trained with {:?} but the settings ask for {:?}, train into a \
+                 different database or policy instead",
+                stored, wanted
+            ),
+        }
+    }
+}
+
+impl std::error::Error for SchemaError {}
+
+#[derive(Deserialize)]
+struct VersionOnly {
+    version: u32,
+}
+
+// reads a database in any layout this crate has ever written. returns the
+// version it was found in along with the envelope it migrates to
+pub fn migrate(bytes: &[u8], encoding: Encoding) -> Result<(u32, Envelope), SchemaError> {
+    // cheap to read, and decides which full decode to try
+    let version = decode::<VersionOnly>(bytes, encoding).map(|found| found.version);
+    if version == Some(SCHEMA_VERSION) {
+        if let Some(envelope) = decode::<Envelope>(bytes, encoding) {
+            return Ok((SCHEMA_VERSION, envelope));
+        }
+    }
+    // version 0 was only ever written as ron
+    if encoding == Encoding::Ron {
+        if let Some(tables) = legacy::read_old_tables(bytes) {
+            let mut envelope = Envelope::new(tables);
+            envelope.metadata.before_apple_fix = envelope.tables.keys().cloned().collect();
+            envelope.metadata.before_apple_fix.sort();
+            return Ok((0, envelope));
+        }
+    }
+    match version {
+        Some(version) if version > SCHEMA_VERSION => Err(SchemaError::TooNew(version)),
+        _ => Err(SchemaError::Unreadable),
+    }
+}
+
+fn decode<T: serde::de::DeserializeOwned>(bytes: &[u8], encoding: Encoding) -> Option<T> {
+    match encoding {
+        Encoding::Ron => ron::de::from_bytes(bytes).ok(),
+        Encoding::Bincode => bincode::deserialize(bytes).ok(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::snake::Action;
+    use crate::{MapState, MyState, PackedState};
+
+    const ENCODINGS: [Encoding; 2] = [Encoding::Ron, Encoding::Bincode];
+
+    fn encode<T: Serialize>(value: &T, encoding: Encoding) -> Vec<u8> {
+        match encoding {
+            Encoding::Ron => ron::to_string(value).unwrap().into_bytes(),
+            Encoding::Bincode => bincode::serialize(value).unwrap(),
+        }
+    }
+
+    fn state() -> PackedState {
+        let mut state = MyState::new(3);
+        state.map[0][0] = MapState::Death;
+        state.curr_apple = (1, -1);
+        return PackedState::from(&state);
+    }
+
+    fn tables() -> HashMap<usize, QTable> {
+        let mut table = HashMap::new();
+        table.insert(state(), vec![(Action::XPos, 1.5)].into_iter().collect());
+        let mut tables = HashMap::new();
+        tables.insert(3, table);
+        return tables;
+    }
+
+    #[test]
+    fn reads_version_0() {
+        let old = "{3: {(map: [[Death, Empty, Empty], [Empty, Empty, Empty], \
+                   [Empty, Empty, Empty]], curr_apple: (1, -1), reward: Val(-0.01)): \
+                   {XPos: 1.5}}}";
+        let (version, envelope) = migrate(old.as_bytes(), Encoding::Ron).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(envelope.version, SCHEMA_VERSION);
+        assert_eq!(envelope.tables, tables());
+        assert!(envelope.visits.is_empty());
+        assert_eq!(envelope.metadata.state_encoding, None);
+        assert_eq!(envelope.metadata.before_apple_fix, vec![3]);
+    }
+
+    #[test]
+    fn reads_the_current_version() {
+        for encoding in ENCODINGS.iter() {
+            let mut current = Envelope::new(tables());
+            current.metadata.before_apple_fix = vec![3];
+            let mut visits = HashMap::new();
+            visits.insert(state(), vec![(Action::XPos, 7)].into_iter().collect());
+            current.visits.insert(3, visits);
+            let (version, envelope) = migrate(&encode(&current, *encoding), *encoding).unwrap();
+            assert_eq!(version, SCHEMA_VERSION);
+            assert_eq!(envelope, current);
+        }
+    }
+
+    #[test]
+    fn refuses_newer_versions() {
+        for encoding in ENCODINGS.iter() {
+            let mut newer = Envelope::default();
+            newer.version = SCHEMA_VERSION + 1;
+            match migrate(&encode(&newer, *encoding), *encoding) {
+                Err(SchemaError::TooNew(version)) => assert_eq!(version, SCHEMA_VERSION + 1),
+                other => panic!("expected TooNew, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn refuses_anything_else() {
+        // any four bytes pass for a bincode version, so too few are needed to
+        // tell it apart from a newer database
+        let garbage: [&[u8]; 2] = [b"not a database", b"no"];
+        for (bytes, encoding) in garbage.iter().zip(ENCODINGS.iter()) {
+            match migrate(bytes, *encoding) {
+                Err(SchemaError::Unreadable) => (),
+                other => panic!("expected Unreadable, got {:?}", other),
+            }
+        }
+    }
+
+    #[test]
+    fn checks_the_state_encoding() {
+        let path = std::env::temp_dir().join(format!("snake_ai_{}_schema.ron", std::process::id()));
+        let db = crate::open_database(&path, 0);
+        std::fs::remove_file(&path).unwrap();
+        let mut config = crate::settings::Settings::default().config(3, db, None);
+        let mut envelope = Envelope::default();
+        envelope.check_state_encoding(&config).unwrap();
+        envelope.record_training(&config);
+        envelope.check_state_encoding(&config).unwrap();
+        config.symmetry = !config.symmetry;
+        match envelope.check_state_encoding(&config) {
+            Err(SchemaError::StateEncodingChanged { .. }) => (),
+            other => panic!("expected StateEncodingChanged, got {:?}", other),
+        }
+    }
+}
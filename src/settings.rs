@@ -0,0 +1,306 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+use toml::value::{Table, Value};
+
+use crate::metrics::SharedMetrics;
+use crate::packed::MAX_BOUND;
+use crate::policy::PolicyStore;
+use crate::{
+    ActionMode, AppleFeatures, Config, Encoding, QLearning, RewardConfig, SharedDatabase,
+    StarvationLimit,
+};
+
+// read when no other config file is given, if it exists
+pub const DEFAULT_PATH: &str = "snake_ai.toml";
+
+// everything the command line needs to build a Config, as read from a toml
+// file. keys left out of the file keep their defaults, and the optional
+// ones are off unless given
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Settings {
+    // train runs workers_per_bound threads for every bound, the other
+    // commands use the first one
+    pub bounds: Vec<usize>,
+    pub workers_per_bound: usize,
+    pub arena_size: (i32, i32),
+    pub seed: Option<u64>,
+    pub apple_features: AppleFeatures,
+    pub action_mode: ActionMode,
+    pub symmetry: bool,
+    pub rewards: RewardConfig,
+    pub starvation: Option<StarvationLimit>,
+    pub learning: QLearning,
+    // a database file, or with policy set the directory holding one per policy
+    pub database: PathBuf,
+    pub policy: Option<String>,
+    // used for policies that don't exist yet. a database file named directly
+    // is encoded according to its extension, see Encoding::from_path
+    pub encoding: Encoding,
+    // old copies of the database kept next to it, one more per save
+    pub checkpoints: usize,
+    pub metrics: Option<PathBuf>,
+    // how long train keeps going, 0 for forever
+    pub train_minutes: u32,
+    // milliseconds between ticks in the terminal
+    pub tick_ms: u64,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            bounds: vec![3, 5, 7, 9],
+            workers_per_bound: 1,
+            arena_size: (16, 16),
+            seed: None,
+            apple_features: AppleFeatures::default(),
+            action_mode: ActionMode::Absolute,
+            symmetry: false,
+            rewards: RewardConfig::default(),
+            starvation: None,
+            learning: QLearning::new(0.1, 0.01, 2.),
+            database: PathBuf::from("snake_ai_database.ron"),
+            policy: None,
+            encoding: Encoding::Ron,
+            checkpoints: 3,
+            metrics: None,
+            train_minutes: 0,
+            tick_ms: 100,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum SettingsError {
+    Io(std::io::Error),
+    Parse(toml::de::Error),
+    Serialize(toml::ser::Error),
+    // an override named something that isn't a table
+    NotATable(String),
+    // readable, but not something the game can run with
+    Invalid(String),
+}
+
+impl std::fmt::Display for SettingsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            SettingsError::Io(e) => write!(f, "io error: {}", e),
+            SettingsError::Parse(e) => write!(f, "invalid settings: {}", e),
+            SettingsError::Serialize(e) => write!(f, "toml error: {}", e),
+            SettingsError::NotATable(key) => write!(f, "{} has no fields to set", key),
+            SettingsError::Invalid(reason) => write!(f, "invalid settings: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for SettingsError {}
+
+impl From<std::io::Error> for SettingsError {
+    fn from(e: std::io::Error) -> Self {
+        SettingsError::Io(e)
+    }
+}
+
+impl From<toml::de::Error> for SettingsError {
+    fn from(e: toml::de::Error) -> Self {
+        SettingsError::Parse(e)
+    }
+}
+
+impl From<toml::ser::Error> for SettingsError {
+    fn from(e: toml::ser::Error) -> Self {
+        SettingsError::Serialize(e)
+    }
+}
+
+impl Settings {
+    // reads path, or DEFAULT_PATH if that exists, or falls back to the defaults
+    pub fn load(path: Option<&Path>) -> Result<Settings, SettingsError> {
+        let path = match path {
+            Some(path) => path,
+            None if Path::new(DEFAULT_PATH).exists() => Path::new(DEFAULT_PATH),
+            None => return Ok(Settings::default()),
+        };
+        let text = std::fs::read_to_string(path)?;
+        let settings: Settings = toml::from_str(&text)?;
+        settings.validate()?;
+        return Ok(settings);
+    }
+
+    // sets one field the way it would be written in the file, with dots to
+    // reach into tables: ("learning.alpha", "0.2"), ("arena_size", "[20, 20]").
+    // values that aren't valid toml are taken as strings, and "none" turns an
+    // optional field off
+    pub fn set(&mut self, key: &str, value: &str) -> Result<(), SettingsError> {
+        let mut root = match Value::try_from(&*self)? {
+            Value::Table(table) => table,
+            _ => unreachable!(),
+        };
+
+        let parts: Vec<String> = key.split('.').map(|part| part.replace('-', "_")).collect();
+        let (last, tables) = parts.split_last().unwrap();
+        let mut table = &mut root;
+        for part in tables {
+            table = match table
+                .entry(part.clone())
+                .or_insert_with(|| Value::Table(Table::new()))
+            {
+                Value::Table(inner) => inner,
+                _ => return Err(SettingsError::NotATable(part.clone())),
+            };
+        }
+        if value == "none" {
+            table.remove(last);
+        } else {
+            table.insert(last.clone(), parse_value(value));
+        }
+
+        let settings: Settings = Value::Table(root).try_into()?;
+        settings.validate()?;
+        *self = settings;
+        return Ok(());
+    }
+
+    // catches what would otherwise panic somewhere deep in the game
+    pub fn validate(&self) -> Result<(), SettingsError> {
+        let invalid = |reason: String| Err(SettingsError::Invalid(reason));
+        if self.bounds.is_empty() {
+            return invalid("bounds needs at least one bound".to_string());
+        }
+        for bound in self.bounds.iter() {
+            if bound % 2 == 0 || *bound > MAX_BOUND {
+                return invalid(format!(
+                    "bound {} must be odd and at most {}",
+                    bound, MAX_BOUND
+                ));
+            }
+        }
+        if self.arena_size.0 < 4 || self.arena_size.1 < 4 {
+            return invalid(format!(
+                "arena_size {:?} must be at least 4 by 4",
+                self.arena_size
+            ));
+        }
+        if let Some(width) = self.apple_features.distance_bucket {
+            if width <= 0 {
+                return invalid(format!(
+                    "apple_features.distance_bucket {} must be positive",
+                    width
+                ));
+            }
+        }
+        if self.workers_per_bound == 0 {
+            return invalid("workers_per_bound must be at least 1".to_string());
+        }
+        return Ok(());
+    }
+
+    // the file the chosen policy is kept in
+    pub fn database_path(&self) -> std::io::Result<PathBuf> {
+        match &self.policy {
+            Some(name) => PolicyStore::new(&self.database, self.encoding)?.path(name),
+            None => Ok(self.database.clone()),
+        }
+    }
+
+    pub fn config(
+        &self,
+        bound: usize,
+        db: SharedDatabase,
+        metrics: Option<SharedMetrics>,
+    ) -> Config {
+        return Config {
+            bound: bound,
+            arena_size: self.arena_size,
+            seed: self.seed,
+            apple_features: self.apple_features,
+            action_mode: self.action_mode,
+            symmetry: self.symmetry,
+            rewards: self.rewards,
+            starvation: self.starvation,
+            learning: self.learning,
+            db: db,
+            metrics: metrics,
+            tables: None,
+        };
+    }
+}
+
+fn parse_value(value: &str) -> Value {
+    match format!("value = {}", value).parse::<Value>() {
+        Ok(Value::Table(mut table)) => table.remove("value").unwrap(),
+        _ => Value::String(value.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn invalid(key: &str, value: &str) {
+        let mut settings = Settings::default();
+        match settings.set(key, value) {
+            Err(SettingsError::Invalid(_)) => (),
+            other => panic!("{} = {} gave {:?}", key, value, other),
+        }
+        assert_eq!(settings, Settings::default());
+    }
+
+    #[test]
+    fn defaults_are_valid() {
+        Settings::default().validate().unwrap();
+    }
+
+    #[test]
+    fn shipped_file_is_valid() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join(DEFAULT_PATH);
+        Settings::load(Some(&path)).unwrap();
+    }
+
+    #[test]
+    fn overrides_reach_into_tables() {
+        let mut settings = Settings::default();
+        settings.set("learning.alpha", "0.25").unwrap();
+        settings.set("arena-size", "[20, 12]").unwrap();
+        settings.set("policy", "baseline").unwrap();
+        settings.set("seed", "4").unwrap();
+        assert_eq!(settings.learning.alpha, 0.25);
+        assert_eq!(settings.arena_size, (20, 12));
+        assert_eq!(settings.policy, Some("baseline".to_string()));
+        settings.set("seed", "none").unwrap();
+        assert_eq!(settings.seed, None);
+    }
+
+    #[test]
+    fn unknown_settings_are_refused() {
+        let mut settings = Settings::default();
+        assert!(settings.set("speed", "2").is_err());
+        match settings.set("bounds.first", "3") {
+            Err(SettingsError::NotATable(key)) => assert_eq!(key, "bounds"),
+            other => panic!("expected NotATable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn bad_values_are_refused() {
+        invalid("bounds", "[]");
+        invalid("bounds", "[3, 4]");
+        invalid("bounds", "[13]");
+        invalid("arena_size", "[3, 16]");
+        invalid("apple_features.distance_bucket", "0");
+        invalid("workers_per_bound", "0");
+    }
+
+    #[test]
+    fn bad_files_are_refused() {
+        let path = std::env::temp_dir().join(format!("snake_ai_{}_bad.toml", std::process::id()));
+        std::fs::write(&path, "bounds = [3, 6]\n").unwrap();
+        let loaded = Settings::load(Some(&path));
+        std::fs::remove_file(&path).unwrap();
+        match loaded {
+            Err(SettingsError::Invalid(_)) => (),
+            other => panic!("expected Invalid, got {:?}", other),
+        }
+    }
+}
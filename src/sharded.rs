@@ -0,0 +1,340 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+
+use crate::snake::Action;
+use crate::{
+    read_table, read_visits, write_table, PackedState, QTable, SharedDatabase, VisitTable,
+};
+
+// enough that workers training the same bound rarely wait on each other
+pub const DEFAULT_SHARDS: usize = 64;
+
+#[derive(Default)]
+struct Shard {
+    values: QTable,
+    visits: VisitTable,
+}
+
+// the values and visit counts for one bound, split by the hash of the state
+// into shards that are locked separately, so any number of trainers can read
+// and update it at once
+pub struct ShardedTable {
+    shards: Vec<RwLock<Shard>>,
+}
+
+impl ShardedTable {
+    pub fn new(shards: usize) -> ShardedTable {
+        return ShardedTable {
+            shards: (0..shards.max(1))
+                .map(|_| RwLock::new(Shard::default()))
+                .collect(),
+        };
+    }
+
+    pub fn from_tables(values: QTable, visits: VisitTable, shards: usize) -> ShardedTable {
+        let table = ShardedTable::new(shards);
+        for (state, actions) in values {
+            table
+                .shard(&state)
+                .write()
+                .unwrap()
+                .values
+                .insert(state, actions);
+        }
+        for (state, actions) in visits {
+            table
+                .shard(&state)
+                .write()
+                .unwrap()
+                .visits
+                .insert(state, actions);
+        }
+        return table;
+    }
+
+    // picked on every step, so the packed bits are mixed with a multiply
+    // rather than run through a full hasher
+    fn shard(&self, state: &PackedState) -> &RwLock<Shard> {
+        let folded = state.map as u64 ^ (state.map >> 64) as u64 ^ (state.features as u64) << 32;
+        let mixed = folded.wrapping_mul(0x9e37_79b9_7f4a_7c15) >> 32;
+        return &self.shards[mixed as usize % self.shards.len()];
+    }
+
+    // a copy of the values for state, None if it was never seen
+    pub fn values(&self, state: &PackedState) -> Option<HashMap<Action, f64>> {
+        return self.shard(state).read().unwrap().values.get(state).cloned();
+    }
+
+    // the highest value learned for state, None if it was never seen
+    pub fn max_value(&self, state: &PackedState) -> Option<f64> {
+        let shard = self.shard(state).read().unwrap();
+        return shard
+            .values
+            .get(state)?
+            .values()
+            .cloned()
+            .fold(None, |best, value| match best {
+                Some(best) if best >= value => Some(best),
+                _ => Some(value),
+            });
+    }
+
+    // the action with the highest learned value, None if state was never seen.
+    // ties go to whichever comes first in state.actions(), so the same table
+    // always picks the same action
+    pub fn best_action(&self, state: &PackedState) -> Option<Action> {
+        let shard = self.shard(state).read().unwrap();
+        let values = shard.values.get(state)?;
+        let mut best: Option<(Action, f64)> = None;
+        for action in state.actions() {
+            let value = match values.get(&action) {
+                Some(value) => *value,
+                None => continue,
+            };
+            match best {
+                Some((_, best_value)) if best_value >= value => {}
+                _ => best = Some((action, value)),
+            }
+        }
+        return best.map(|(action, _)| action);
+    }
+
+    pub fn value(&self, state: &PackedState, action: &Action) -> Option<f64> {
+        let shard = self.shard(state).read().unwrap();
+        return shard.values.get(state).and_then(|m| m.get(action)).cloned();
+    }
+
+    pub fn visits(&self, state: &PackedState, action: &Action) -> u64 {
+        let shard = self.shard(state).read().unwrap();
+        return shard
+            .visits
+            .get(state)
+            .and_then(|m| m.get(action))
+            .cloned()
+            .unwrap_or(0);
+    }
+
+    // sets the value of action in state to whatever update makes of its
+    // current value, and counts the visit. nothing else can touch the value
+    // in between. returns true if state had no values before, so whoever
+    // made the call is the one who found it
+    pub fn update<F: FnOnce(Option<f64>) -> f64>(
+        &self,
+        state: PackedState,
+        action: Action,
+        update: F,
+    ) -> bool {
+        let mut shard = self.shard(&state).write().unwrap();
+        let new = !shard.values.contains_key(&state);
+        let values = shard.values.entry(state).or_insert_with(HashMap::new);
+        let value = update(values.get(&action).cloned());
+        values.insert(action, value);
+        *shard
+            .visits
+            .entry(state)
+            .or_insert_with(HashMap::new)
+            .entry(action)
+            .or_insert(0) += 1;
+        return new;
+    }
+
+    pub fn shards(&self) -> usize {
+        return self.shards.len();
+    }
+
+    // number of states with learned values
+    pub fn len(&self) -> usize {
+        return self
+            .shards
+            .iter()
+            .map(|shard| shard.read().unwrap().values.len())
+            .sum();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+
+    // copies of everything in the table. each shard is copied in one go, but
+    // trainers may carry on with the others in the meantime
+    pub fn snapshot(&self) -> (QTable, VisitTable) {
+        let mut values = HashMap::new();
+        let mut visits = HashMap::new();
+        for shard in self.shards.iter() {
+            let shard = shard.read().unwrap();
+            values.extend(shard.values.iter().map(|(k, v)| (*k, v.clone())));
+            visits.extend(shard.visits.iter().map(|(k, v)| (*k, v.clone())));
+        }
+        return (values, visits);
+    }
+}
+
+// the live tables training works on, one per bound, loaded from db the first
+// time a bound is asked for. db only sees what was learned once snapshot is
+// called, and only has it on disk once it is saved after that
+pub struct SharedTables {
+    db: SharedDatabase,
+    shards: usize,
+    bounds: RwLock<HashMap<usize, Arc<ShardedTable>>>,
+}
+
+impl SharedTables {
+    pub fn new(db: SharedDatabase, shards: usize) -> SharedTables {
+        return SharedTables {
+            db: db,
+            shards: shards,
+            bounds: RwLock::new(HashMap::new()),
+        };
+    }
+
+    pub fn bound(&self, bound: usize) -> Arc<ShardedTable> {
+        if let Some(table) = self.bounds.read().unwrap().get(&bound) {
+            return table.clone();
+        }
+        let db = &self.db;
+        let shards = self.shards;
+        // another thread may have loaded it since the read lock was released
+        let mut bounds = self.bounds.write().unwrap();
+        return bounds
+            .entry(bound)
+            .or_insert_with(|| {
+                Arc::new(ShardedTable::from_tables(
+                    read_table(db, bound),
+                    read_visits(db, bound),
+                    shards,
+                ))
+            })
+            .clone();
+    }
+
+    // copies every live table into db, replacing what it held for those
+    // bounds
+    pub fn snapshot(&self) {
+        let bounds = self.bounds.read().unwrap();
+        for (bound, table) in bounds.iter() {
+            write_table(&self.db, *bound, Some(table.snapshot()));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{open_database, MyState};
+
+    fn state(i: i32) -> PackedState {
+        let mut state = MyState::new(5);
+        state.apple_distance = i;
+        return PackedState::from(&state);
+    }
+
+    fn values(pairs: &[(Action, f64)]) -> HashMap<Action, f64> {
+        return pairs.iter().cloned().collect();
+    }
+
+    #[test]
+    fn best_action_breaks_ties_in_action_order() {
+        let mut table = HashMap::new();
+        table.insert(
+            state(0),
+            values(&[
+                (Action::YNeg, 2.0),
+                (Action::YPos, 2.0),
+                (Action::XNeg, 1.0),
+            ]),
+        );
+        let table = ShardedTable::from_tables(table, HashMap::new(), 4);
+        assert_eq!(table.best_action(&state(0)), Some(Action::YPos));
+        assert_eq!(table.max_value(&state(0)), Some(2.0));
+        assert_eq!(table.best_action(&state(1)), None);
+        assert_eq!(table.max_value(&state(1)), None);
+    }
+
+    #[test]
+    fn update_counts_visits_and_new_states() {
+        let table = ShardedTable::new(4);
+        assert!(table.update(state(0), Action::XPos, |current| {
+            assert_eq!(current, None);
+            1.0
+        }));
+        assert!(!table.update(state(0), Action::YPos, |_| 2.0));
+        assert!(!table.update(state(0), Action::XPos, |current| current.unwrap() + 1.0));
+        assert_eq!(table.value(&state(0), &Action::XPos), Some(2.0));
+        assert_eq!(table.visits(&state(0), &Action::XPos), 2);
+        assert_eq!(table.visits(&state(0), &Action::XNeg), 0);
+        assert_eq!(table.len(), 1);
+    }
+
+    #[test]
+    fn concurrent_updates_are_not_lost() {
+        let table = Arc::new(ShardedTable::new(8));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let table = table.clone();
+                std::thread::spawn(move || {
+                    let mut new = 0;
+                    for i in 0..1000 {
+                        if table.update(state(i % 50), Action::XPos, |v| v.unwrap_or(0.0) + 1.0) {
+                            new += 1;
+                        }
+                    }
+                    new
+                })
+            })
+            .collect();
+        let new: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+
+        // each state was found by exactly one thread
+        assert_eq!(new, 50);
+        assert_eq!(table.len(), 50);
+        for i in 0..50 {
+            assert_eq!(table.value(&state(i), &Action::XPos), Some(160.0));
+            assert_eq!(table.visits(&state(i), &Action::XPos), 160);
+        }
+    }
+
+    #[test]
+    fn snapshots_while_training_hold_whole_updates() {
+        let table = Arc::new(ShardedTable::new(8));
+        let trainer = {
+            let table = table.clone();
+            std::thread::spawn(move || {
+                for i in 0..20000 {
+                    table.update(state(i % 200), Action::XPos, |v| v.unwrap_or(0.0) + 1.0);
+                }
+            })
+        };
+        for _ in 0..20 {
+            let (values, visits) = table.snapshot();
+            // a value and its visit count change under the same lock
+            for (state, actions) in values {
+                assert_eq!(actions[&Action::XPos] as u64, visits[&state][&Action::XPos]);
+            }
+        }
+        trainer.join().unwrap();
+        let (values, visits) = table.snapshot();
+        assert_eq!(values.len(), 200);
+        assert_eq!(visits[&state(7)][&Action::XPos], 100);
+    }
+
+    #[test]
+    fn shared_tables_load_once_and_snapshot_into_the_database() {
+        let path = std::env::temp_dir().join(format!("snake_ai_{}_shared.ron", std::process::id()));
+        let db = open_database(&path, 0);
+        let mut stored = HashMap::new();
+        stored.insert(state(0), values(&[(Action::XPos, 1.0)]));
+        write_table(&db, 3, Some((stored, HashMap::new())));
+
+        let tables = SharedTables::new(db.clone(), 4);
+        let table = tables.bound(3);
+        assert!(Arc::ptr_eq(&table, &tables.bound(3)));
+        assert_eq!(table.value(&state(0), &Action::XPos), Some(1.0));
+
+        tables.bound(5).update(state(1), Action::YPos, |_| 3.0);
+        tables.snapshot();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(read_table(&db, 3), table.snapshot().0);
+        assert_eq!(read_table(&db, 5)[&state(1)][&Action::YPos], 3.0);
+        assert_eq!(read_visits(&db, 5)[&state(1)][&Action::YPos], 1);
+    }
+}
@@ -1,9 +1,10 @@
-use glium::{glutin, Surface};
-use rand::Rng;
-use rust_lm::Mat4;
+use rand::{Rng, SeedableRng};
+use rand_chacha::ChaCha8Rng;
 use serde::{Deserialize, Serialize};
 
-use crate::{Fake, MapState, MyState};
+use crate::packed::PackedState;
+use crate::symmetry::{canonicalise, Symmetry};
+use crate::{ActionMode, AppleFeatures, MapState, MyState, RewardConfig, StarvationLimit};
 
 #[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
 pub enum Action {
@@ -11,12 +12,90 @@ pub enum Action {
     XNeg,
     YPos,
     YNeg,
+    // relative to the way the snake is heading
+    TurnLeft,
+    Straight,
+    TurnRight,
 }
 
-#[derive(Copy, Clone)]
-struct Vertex {
-    position: [f32; 2],
-    vec_color: (f32, f32, f32),
+impl Action {
+    // the absolute move this action makes for a snake heading that way,
+    // absolute actions come back unchanged
+    pub fn resolve(self, heading: Action) -> Action {
+        match self {
+            Action::TurnLeft => heading.turn_left(),
+            Action::Straight => heading,
+            Action::TurnRight => heading.turn_right(),
+            _ => self,
+        }
+    }
+
+    fn turn_left(self) -> Action {
+        match self {
+            Action::YPos => Action::XNeg,
+            Action::XNeg => Action::YNeg,
+            Action::YNeg => Action::XPos,
+            Action::XPos => Action::YPos,
+            _ => self,
+        }
+    }
+
+    fn turn_right(self) -> Action {
+        match self {
+            Action::YPos => Action::XPos,
+            Action::XPos => Action::YNeg,
+            Action::YNeg => Action::XNeg,
+            Action::XNeg => Action::YPos,
+            _ => self,
+        }
+    }
+}
+
+// rotates an offset from the arena into the frame of a snake heading that way,
+// where straight ahead is +y and its right hand side is +x
+fn to_local(heading: Action, offset: (i32, i32)) -> (i32, i32) {
+    match heading {
+        Action::XPos => (-offset.1, offset.0),
+        Action::YNeg => (-offset.0, -offset.1),
+        Action::XNeg => (offset.1, -offset.0),
+        _ => offset,
+    }
+}
+
+// the inverse of to_local
+fn to_world(heading: Action, offset: (i32, i32)) -> (i32, i32) {
+    match heading {
+        Action::XPos => (offset.1, -offset.0),
+        Action::YNeg => (-offset.0, -offset.1),
+        Action::XNeg => (-offset.1, offset.0),
+        _ => offset,
+    }
+}
+
+#[derive(PartialEq, Eq, Hash, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum DeathCause {
+    Wall,
+    Body,
+    // went longer than the StarvationLimit without eating
+    Starvation,
+}
+
+// what a single tick did to the snake
+#[derive(PartialEq, Copy, Clone, Debug, Serialize, Deserialize)]
+pub enum TickOutcome {
+    Moved,
+    Ate,
+    Died {
+        cause: DeathCause,
+        final_length: usize,
+        steps: u32,
+    },
+}
+
+impl TickOutcome {
+    pub fn died(&self) -> bool {
+        return matches!(self, TickOutcome::Died { .. });
+    }
 }
 
 pub struct Arena {
@@ -27,22 +106,41 @@ pub struct Arena {
     pub arena_size: (i32, i32),
     pub reward_for_last_action: f64,
     pub state: MyState,
+    // state packed down to what the learned tables are keyed by
+    pub key: PackedState,
+    // why the snake died on the last tick, None if it survived
+    pub last_death: Option<DeathCause>,
+    // ticks played (the fatal one included) and apples eaten since the last reset
+    pub steps: u32,
+    pub score: u32,
+    // ticks since the last apple was eaten, and which tiles the head has been
+    // on in that time (indexed x * height + y)
+    steps_since_apple: u32,
+    visited: Vec<bool>,
+    rewards: RewardConfig,
+    starvation: Option<StarvationLimit>,
+    // start a new game as soon as the snake dies instead of waiting for reset()
+    auto_reset: bool,
+    // the snake died and the game hasn't been reset yet
+    finished: bool,
     bound: usize,
-    display: Option<glium::Display>,
-    program: Option<glium::Program>,
-    transform_matrix: Mat4,
-    render: bool,
+    apple_features: AppleFeatures,
+    action_mode: ActionMode,
+    // whether state is canonicalised, and the transform that took the
+    // arena's view of the game to state
+    symmetric: bool,
+    frame: Symmetry,
+    seed: u64,
+    // drives apple placement
+    rng: ChaCha8Rng,
+    // drives random exploration, kept apart so exploring doesn't move the apples
+    explore_rng: ChaCha8Rng,
 }
 
 impl Arena {
-    pub fn new(arena_size: (i32, i32), bound: usize) -> Arena {
-        let transform_matrix = Mat4::identity()
-            .scale_by(
-                2.0 / (arena_size.0 as f32),
-                2.0 / (arena_size.1 as f32),
-                1.0,
-            )
-            .translate_by(-1.0, -1.0, 0.0);
+    // seed: None picks a fresh seed, which can be read back with seed()
+    pub fn new(arena_size: (i32, i32), bound: usize, seed: Option<u64>) -> Arena {
+        let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
 
         let mut out = Arena {
             snake: Vec::new(),
@@ -50,61 +148,29 @@ impl Arena {
             arena_size: arena_size,
             reward_for_last_action: 0.0,
             state: MyState::new(bound),
+            key: PackedState::from(&MyState::new(bound)),
+            last_death: None,
+            steps: 0,
+            score: 0,
+            steps_since_apple: 0,
+            visited: vec![false; (arena_size.0 * arena_size.1) as usize],
+            rewards: RewardConfig::default(),
+            starvation: None,
+            auto_reset: false,
+            finished: false,
             bound: bound,
-            display: None,
-            program: None,
-            transform_matrix: transform_matrix,
-            render: false,
+            apple_features: AppleFeatures::default(),
+            action_mode: ActionMode::Absolute,
+            symmetric: false,
+            frame: Symmetry::IDENTITY,
+            seed: seed,
+            rng: ChaCha8Rng::seed_from_u64(seed),
+            explore_rng: ChaCha8Rng::seed_from_u64(seed.wrapping_add(1)),
         };
         out.reset();
         return out;
     }
 
-    pub fn new_render(
-        arena_size: (i32, i32),
-        bound: usize,
-        events_loop: &glutin::event_loop::EventLoop<()>,
-    ) -> Arena {
-        let wb = glium::glutin::window::WindowBuilder::new()
-            .with_inner_size(glium::glutin::dpi::LogicalSize::new(640.0, 640.0))
-            .with_title("snake");
-        let cb = glium::glutin::ContextBuilder::new().with_vsync(true);
-        let display = glium::Display::new(wb, cb, events_loop).unwrap();
-
-        implement_vertex!(Vertex, position, vec_color);
-
-        let vertex_shader_src = r#"
-        #version 140
-        in vec2 position;
-        in vec3 vec_color;
-        out vec3 my_color;
-        uniform mat4 matrix;
-        void main() {
-            my_color = vec_color;
-            gl_Position = matrix * vec4(position, 0.0, 1.0);
-        }
-    "#;
-
-        let fragment_shader_src = r#"
-        #version 140
-        in vec3 my_color;
-        out vec4 color;
-        void main() {
-            color = vec4(my_color, 1.0);
-        }
-    "#;
-
-        let program =
-            glium::Program::from_source(&display, vertex_shader_src, fragment_shader_src, None)
-                .unwrap();
-
-        let mut out = Self::new(arena_size, bound);
-        out.display = Some(display);
-        out.program = Some(program);
-        out.render = true;
-        return out;
-    }
-
     fn new_snake(&mut self) {
         let mut new_snake = Vec::new();
         new_snake.push((self.arena_size.0 / 2, (self.arena_size.1 / 2) - 2));
@@ -113,18 +179,123 @@ impl Arena {
         self.snake = new_snake;
     }
 
+    pub fn set_apple_features(&mut self, apple_features: AppleFeatures) {
+        self.apple_features = apple_features;
+        self.update_state();
+    }
+
+    pub fn set_rewards(&mut self, rewards: RewardConfig) {
+        self.rewards = rewards;
+    }
+
+    pub fn rewards(&self) -> RewardConfig {
+        return self.rewards;
+    }
+
+    // None lets the snake go hungry forever
+    pub fn set_starvation(&mut self, starvation: Option<StarvationLimit>) {
+        self.starvation = starvation;
+    }
+
+    pub fn starvation(&self) -> Option<StarvationLimit> {
+        return self.starvation;
+    }
+
+    pub fn set_auto_reset(&mut self, auto_reset: bool) {
+        self.auto_reset = auto_reset;
+    }
+
+    // true once the snake has died, until reset() is called. never true when
+    // auto resetting
+    pub fn finished(&self) -> bool {
+        return self.finished;
+    }
+
+    pub fn set_action_mode(&mut self, action_mode: ActionMode) {
+        self.action_mode = action_mode;
+        self.update_state();
+    }
+
+    pub fn set_symmetric(&mut self, symmetric: bool) {
+        self.symmetric = symmetric;
+        self.update_state();
+    }
+
+    // turns an action chosen for state (which may be rotated, mirrored or
+    // relative to the heading) into the absolute move it stands for
+    pub fn world_action(&self, action: Action) -> Action {
+        return self
+            .frame
+            .inverse()
+            .apply_action(action)
+            .resolve(self.heading());
+    }
+
+    // the direction the head last moved in
+    pub fn heading(&self) -> Action {
+        let head = self.snake[self.snake.len() - 1];
+        let neck = self.snake[self.snake.len() - 2];
+        match (head.0 - neck.0, head.1 - neck.1) {
+            (1, _) => Action::XPos,
+            (-1, _) => Action::XNeg,
+            (_, -1) => Action::YNeg,
+            _ => Action::YPos,
+        }
+    }
+
+    pub fn bound(&self) -> usize {
+        self.bound
+    }
+
+    pub fn seed(&self) -> u64 {
+        self.seed
+    }
+
+    // restarts both random streams from seed and starts a new game
+    pub fn reseed(&mut self, seed: u64) {
+        self.seed = seed;
+        self.rng = ChaCha8Rng::seed_from_u64(seed);
+        self.explore_rng = ChaCha8Rng::seed_from_u64(seed.wrapping_add(1));
+        self.reset();
+    }
+
+    pub fn random_action(&mut self, actions: &[Action]) -> Action {
+        actions[self.explore_rng.gen_range(0..actions.len())]
+    }
+
+    // starts a new game
     pub fn reset(&mut self) {
-        // println!("length at death: {}", self.snake.len());
         self.new_snake();
         self.gen_apple();
+        self.steps = 0;
+        self.score = 0;
+        self.forget_visited();
+        self.last_death = None;
+        self.finished = false;
+        self.reward_for_last_action = 0.0;
+        self.update_state();
+    }
+
+    fn forget_visited(&mut self) {
+        self.steps_since_apple = 0;
+        for tile in self.visited.iter_mut() {
+            *tile = false;
+        }
+        let head = self.snake[self.snake.len() - 1];
+        let index = self.tile_index(head);
+        self.visited[index] = true;
+    }
+
+    fn tile_index(&self, tile: (i32, i32)) -> usize {
+        return (tile.0 * self.arena_size.1 + tile.1) as usize;
     }
 
     fn gen_apple(&mut self) {
         self.apple_pos.2 = false;
         loop {
             let test: (i32, i32) = (
-                rand::thread_rng().gen_range(0..self.arena_size.0),
-                rand::thread_rng().gen_range(0..self.arena_size.1),
+                self.rng.gen_range(0..self.arena_size.0),
+                self.rng.gen_range(0..self.arena_size.1),
             );
             self.apple_pos = (test.0, test.1, true);
             for chunk in self.snake.iter() {
@@ -146,27 +317,38 @@ impl Arena {
         self.update_state();
     }
 
-    fn update_state(&mut self) {
-        self.state.reward = Fake::Val(self.reward_for_last_action);
-
+    pub(crate) fn update_state(&mut self) {
         let head: (i32, i32);
         if let Some(thing) = self.snake.get(self.snake.len() - 1) {
             head = *thing;
         } else {
             panic!();
         }
-        self.state.curr_apple.0 = self.apple_pos.0 - head.0;
-        if self.state.curr_apple.0 > 0 {
-            self.state.curr_apple.0 = 1;
-        } else if self.state.curr_apple.0 < 0 {
-            self.state.curr_apple.0 = -1;
-        }
-        self.state.curr_apple.1 = self.apple_pos.0 - head.0;
-        if self.state.curr_apple.1 > 0 {
-            self.state.curr_apple.1 = 1;
-        } else if self.state.curr_apple.1 < 0 {
-            self.state.curr_apple.1 = -1;
+        // in relative mode everything is seen from the snake's point of view,
+        // otherwise the arena is seen as if the snake were heading up
+        let heading = match self.action_mode {
+            ActionMode::Absolute => Action::YPos,
+            ActionMode::Relative => self.heading(),
+        };
+        self.state.relative = self.action_mode == ActionMode::Relative;
+
+        let apple_offset = to_local(
+            heading,
+            (self.apple_pos.0 - head.0, self.apple_pos.1 - head.1),
+        );
+        if self.apple_features.direction {
+            self.state.curr_apple = (apple_offset.0.signum(), apple_offset.1.signum());
+        } else {
+            self.state.curr_apple = (0, 0);
         }
+        self.state.apple_distance = match self.apple_features.distance_bucket {
+            Some(width) => (apple_offset.0.abs() + apple_offset.1.abs()) / width,
+            None => 0,
+        };
+        let half = self.bound as i32 / 2;
+        self.state.apple_in_window = self.apple_features.in_window
+            && apple_offset.0.abs() <= half
+            && apple_offset.1.abs() <= half;
 
         // populate self.state.map
         {
@@ -184,7 +366,8 @@ impl Arena {
                     //     self.state.map[i as usize][j as usize] = MapState::Empty;
                     //     continue;
                     // }
-                    let test = (i - local_head.0 + head.0, j - local_head.1 + head.1);
+                    let offset = to_world(heading, (i - local_head.0, j - local_head.1));
+                    let test = (head.0 + offset.0, head.1 + offset.1);
                     for item in self.snake.iter() {
                         if item.0 == test.0 && item.1 == test.1 {
                             self.state.map[i as usize][j as usize] = MapState::Death;
@@ -201,10 +384,51 @@ impl Arena {
                 }
             }
         }
+
+        self.frame = Symmetry::IDENTITY;
+        if self.symmetric {
+            let (state, frame) = canonicalise(&self.state);
+            self.state = state;
+            self.frame = frame;
+        }
+        self.key = PackedState::from(&self.state);
     }
 
-    // returns true if the snake died this tick
-    pub fn tick(&mut self, action: Action) -> bool {
+    // the optional parts of RewardConfig for a move that neither killed the
+    // snake nor ate the apple
+    fn shaping(&mut self, old_head: (i32, i32), new_head: (i32, i32)) -> f64 {
+        let mut reward = 0.0;
+
+        let distance = |tile: (i32, i32)| {
+            (self.apple_pos.0 - tile.0).abs() + (self.apple_pos.1 - tile.1).abs()
+        };
+        if distance(new_head) < distance(old_head) {
+            reward += self.rewards.closer_to_apple;
+        } else {
+            reward -= self.rewards.closer_to_apple;
+        }
+
+        let index = self.tile_index(new_head);
+        if self.visited[index] {
+            reward -= self.rewards.revisit_penalty;
+        }
+        self.visited[index] = true;
+
+        if let Some(limit) = self.rewards.hunger_limit {
+            if self.steps_since_apple > limit {
+                reward -= self.rewards.hunger_penalty;
+            }
+        }
+        return reward;
+    }
+
+    // when the snake dies the game is left as it was for the caller to look
+    // at, and has to be reset() before ticking again unless auto resetting
+    pub fn tick(&mut self, action: Action) -> TickOutcome {
+        if self.finished {
+            panic!("Arena::tick called on a finished game, reset() it first");
+        }
+        let action = action.resolve(self.heading());
         let mut new_head = (0, 0);
         if let Some(thing) = self.snake.get(self.snake.len() - 1) {
             new_head.0 = thing.0;
@@ -226,120 +450,67 @@ impl Arena {
             Action::XNeg => {
                 new_head.0 += -1;
             }
+            // resolved into one of the above
+            Action::TurnLeft | Action::Straight | Action::TurnRight => unreachable!(),
         }
 
-        let mut alive = true;
-        alive = alive && new_head.0 >= 0 && new_head.1 >= 0;
-        alive = alive && new_head.0 < self.arena_size.0 && new_head.1 < self.arena_size.1;
-
-        for item in self.snake.iter() {
-            alive = alive && !(item.0 == new_head.0 && item.1 == new_head.1);
+        self.last_death = None;
+        if new_head.0 < 0
+            || new_head.1 < 0
+            || new_head.0 >= self.arena_size.0
+            || new_head.1 >= self.arena_size.1
+        {
+            self.last_death = Some(DeathCause::Wall);
+        } else if self.snake.contains(&new_head) {
+            self.last_death = Some(DeathCause::Body);
         }
 
-        if !alive {
-            self.reset();
-            self.reward_for_last_action = -4.0;
-            self.update_state();
-            self.render();
-            return true;
+        if let Some(cause) = self.last_death {
+            self.steps += 1;
+            return self.die(cause, self.rewards.death);
         }
 
+        let old_head = self.snake[self.snake.len() - 1];
         self.snake.push(new_head);
+        self.steps += 1;
         if new_head.0 == self.apple_pos.0 && new_head.1 == self.apple_pos.1 && self.apple_pos.2 {
             self.gen_apple();
-            self.reward_for_last_action = 4.0;
+            self.score += 1;
+            self.forget_visited();
+            self.reward_for_last_action =
+                self.rewards.apple + self.rewards.length_bonus * self.snake.len() as f64;
+            self.update_state();
+            return TickOutcome::Ate;
         } else {
             self.snake.remove(0);
-            self.reward_for_last_action = -0.01;
+            self.steps_since_apple += 1;
+            self.reward_for_last_action = self.rewards.step + self.shaping(old_head, new_head);
+
+            let starved = self.starvation.map_or(false, |limit| {
+                self.steps_since_apple > limit.ticks(self.arena_size, self.snake.len())
+            });
+            if starved {
+                return self.die(DeathCause::Starvation, self.rewards.starvation);
+            }
         }
         self.update_state();
-        self.render();
-        return false;
+        return TickOutcome::Moved;
     }
 
-    fn render(&self) {
-        if !self.render {
-            return;
-        }
-        if let Some(display) = &self.display {
-            if let Some(program) = &self.program {
-                let mut target = display.draw();
-
-                target.clear_color(0.0, 0.0, 0.0, 1.0);
-
-                let side_length = 1.0f32;
-                let mut points: Vec<[f32; 2]> = Vec::new();
-
-                for thing in self.snake.iter() {
-                    points.push([thing.0 as f32, thing.1 as f32]);
-                    points.push([thing.0 as f32, thing.1 as f32 + side_length]);
-                    points.push([thing.0 as f32 + side_length, thing.1 as f32 + side_length]);
-
-                    points.push([thing.0 as f32 + side_length, thing.1 as f32 + side_length]);
-                    points.push([thing.0 as f32 + side_length, thing.1 as f32]);
-                    points.push([thing.0 as f32, thing.1 as f32]);
-                }
-                let mut points_proper = points_to_points_proper(points, (0.0, 0.5, 0.0));
-
-                if self.apple_pos.2 {
-                    let mut points: Vec<[f32; 2]> = Vec::new();
-                    points.push([self.apple_pos.0 as f32, self.apple_pos.1 as f32]);
-                    points.push([
-                        self.apple_pos.0 as f32,
-                        self.apple_pos.1 as f32 + side_length,
-                    ]);
-                    points.push([
-                        self.apple_pos.0 as f32 + side_length,
-                        self.apple_pos.1 as f32 + side_length,
-                    ]);
-
-                    points.push([
-                        self.apple_pos.0 as f32 + side_length,
-                        self.apple_pos.1 as f32 + side_length,
-                    ]);
-                    points.push([
-                        self.apple_pos.0 as f32 + side_length,
-                        self.apple_pos.1 as f32,
-                    ]);
-                    points.push([self.apple_pos.0 as f32, self.apple_pos.1 as f32]);
-
-                    points_proper.append(&mut points_to_points_proper(points, (1.0, 0.0, 0.0)));
-                }
-
-                let uniforms = uniform! {
-                    matrix: self.transform_matrix.matrix,
-                };
-
-                let vertex_buffer = glium::VertexBuffer::new(display, &points_proper).unwrap();
-                let index_buffer =
-                    glium::index::NoIndices(glium::index::PrimitiveType::TrianglesList);
-                target
-                    .draw(
-                        &vertex_buffer,
-                        &index_buffer,
-                        program,
-                        &uniforms,
-                        &Default::default(),
-                    )
-                    .unwrap();
-
-                target.finish().unwrap();
-            } else {
-                panic!();
-            }
+    fn die(&mut self, cause: DeathCause, reward: f64) -> TickOutcome {
+        let outcome = TickOutcome::Died {
+            cause: cause,
+            final_length: self.snake.len(),
+            steps: self.steps,
+        };
+        if self.auto_reset {
+            self.reset();
         } else {
-            panic!();
+            self.finished = true;
+            self.update_state();
         }
+        self.last_death = Some(cause);
+        self.reward_for_last_action = reward;
+        return outcome;
     }
 }
-
-fn points_to_points_proper(points: Vec<[f32; 2]>, color: (f32, f32, f32)) -> Vec<Vertex> {
-    let mut points_proper: Vec<Vertex> = Vec::new();
-    for point in points {
-        points_proper.push(Vertex {
-            position: point,
-            vec_color: color,
-        });
-    }
-    return points_proper;
-}
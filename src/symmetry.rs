@@ -0,0 +1,225 @@
+use crate::snake::Action;
+use crate::{MapState, MyState};
+
+// one of the 8 ways of turning and flipping the square window around the head.
+// offsets are mirrored across the y axis first (if mirror) and then rotated
+// a quarter turn counter clockwise rotation times
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Symmetry {
+    rotation: u8,
+    mirror: bool,
+}
+
+impl Symmetry {
+    pub const IDENTITY: Symmetry = Symmetry {
+        rotation: 0,
+        mirror: false,
+    };
+
+    // every transform that keeps states of this kind meaningful. a relative
+    // state always has the heading pointing up, so it can only be mirrored
+    pub fn all(relative: bool) -> Vec<Symmetry> {
+        let mut out = Vec::with_capacity(8);
+        for mirror in [false, true].iter() {
+            let rotations = if relative { 1 } else { 4 };
+            for rotation in 0..rotations {
+                out.push(Symmetry {
+                    rotation: rotation,
+                    mirror: *mirror,
+                });
+            }
+        }
+        return out;
+    }
+
+    pub fn inverse(self) -> Symmetry {
+        if self.mirror {
+            // mirroring then rotating undoes itself
+            return self;
+        }
+        return Symmetry {
+            rotation: (4 - self.rotation) % 4,
+            mirror: false,
+        };
+    }
+
+    pub fn apply_offset(self, offset: (i32, i32)) -> (i32, i32) {
+        let mut out = offset;
+        if self.mirror {
+            out.0 = -out.0;
+        }
+        for _ in 0..self.rotation {
+            out = (-out.1, out.0);
+        }
+        return out;
+    }
+
+    pub fn apply_action(self, action: Action) -> Action {
+        match action {
+            Action::TurnLeft if self.mirror => Action::TurnRight,
+            Action::TurnRight if self.mirror => Action::TurnLeft,
+            Action::TurnLeft | Action::Straight | Action::TurnRight => action,
+            _ => match self.apply_offset(direction(action)) {
+                (1, 0) => Action::XPos,
+                (-1, 0) => Action::XNeg,
+                (0, 1) => Action::YPos,
+                _ => Action::YNeg,
+            },
+        }
+    }
+
+    pub fn apply_state(self, state: &MyState) -> MyState {
+        let mut out = state.clone();
+        let half = state.map.len() as i32 / 2;
+        for i in 0..state.map.len() {
+            for j in 0..state.map.len() {
+                let moved = self.apply_offset((i as i32 - half, j as i32 - half));
+                out.map[(moved.0 + half) as usize][(moved.1 + half) as usize] = state.map[i][j];
+            }
+        }
+        out.curr_apple = self.apply_offset(state.curr_apple);
+        return out;
+    }
+}
+
+fn direction(action: Action) -> (i32, i32) {
+    match action {
+        Action::XPos => (1, 0),
+        Action::XNeg => (-1, 0),
+        Action::YPos => (0, 1),
+        Action::YNeg => (0, -1),
+        _ => (0, 0),
+    }
+}
+
+// picks the same representative for every state that is a turned or flipped
+// copy of another, so they share one entry in the table. returns it together
+// with the transform that took state there
+pub fn canonicalise(state: &MyState) -> (MyState, Symmetry) {
+    let mut best = (state.clone(), Symmetry::IDENTITY);
+    let mut best_key = order_key(state);
+    for symmetry in Symmetry::all(state.relative).into_iter().skip(1) {
+        let candidate = symmetry.apply_state(state);
+        let key = order_key(&candidate);
+        if key < best_key {
+            best_key = key;
+            best = (candidate, symmetry);
+        }
+    }
+    return best;
+}
+
+fn order_key(state: &MyState) -> (Vec<bool>, (i32, i32)) {
+    let mut cells = Vec::with_capacity(state.map.len() * state.map.len());
+    for column in state.map.iter() {
+        for cell in column.iter() {
+            cells.push(*cell == MapState::Death);
+        }
+    }
+    return (cells, state.curr_apple);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ABSOLUTE: [Action; 4] = [Action::XPos, Action::YPos, Action::XNeg, Action::YNeg];
+    const RELATIVE: [Action; 3] = [Action::TurnLeft, Action::Straight, Action::TurnRight];
+
+    // no two of its turned or flipped copies are the same
+    fn lopsided() -> MyState {
+        let mut state = MyState::new(5);
+        state.map[0][1] = MapState::Death;
+        state.map[3][4] = MapState::Death;
+        state.map[4][4] = MapState::Death;
+        state.curr_apple = (1, -1);
+        return state;
+    }
+
+    #[test]
+    fn there_are_eight_transforms() {
+        let all = Symmetry::all(false);
+        assert_eq!(all.len(), 8);
+        assert_eq!(all[0], Symmetry::IDENTITY);
+        for (i, a) in all.iter().enumerate() {
+            for b in all.iter().skip(i + 1) {
+                assert_ne!(a, b);
+            }
+        }
+        assert_eq!(Symmetry::all(true).len(), 2);
+    }
+
+    #[test]
+    fn inverse_undoes_every_transform() {
+        let state = lopsided();
+        for symmetry in Symmetry::all(false) {
+            let inverse = symmetry.inverse();
+            for offset in [(1, 0), (0, 1), (2, -1), (-1, -2)].iter() {
+                let moved = symmetry.apply_offset(*offset);
+                assert_eq!(inverse.apply_offset(moved), *offset);
+            }
+            for action in ABSOLUTE.iter().chain(RELATIVE.iter()) {
+                let moved = symmetry.apply_action(*action);
+                assert_eq!(inverse.apply_action(moved), *action);
+            }
+            assert_eq!(inverse.apply_state(&symmetry.apply_state(&state)), state);
+        }
+    }
+
+    #[test]
+    fn actions_turn_with_the_window() {
+        let half = 2;
+        for symmetry in Symmetry::all(false) {
+            for action in ABSOLUTE.iter() {
+                // death one step away in the direction of action
+                let (x, y) = direction(*action);
+                let mut state = MyState::new(5);
+                state.map[(x + half) as usize][(y + half) as usize] = MapState::Death;
+
+                let moved = symmetry.apply_state(&state);
+                let (x, y) = direction(symmetry.apply_action(*action));
+                assert_eq!(
+                    moved.map[(x + half) as usize][(y + half) as usize],
+                    MapState::Death
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn mirroring_swaps_turns() {
+        let mirror = Symmetry::all(true)[1];
+        assert_eq!(mirror.apply_action(Action::TurnLeft), Action::TurnRight);
+        assert_eq!(mirror.apply_action(Action::Straight), Action::Straight);
+        assert_eq!(mirror.apply_action(Action::TurnRight), Action::TurnLeft);
+    }
+
+    #[test]
+    fn every_copy_has_the_same_representative() {
+        let state = lopsided();
+        let (canonical, symmetry) = canonicalise(&state);
+        assert_eq!(symmetry.apply_state(&state), canonical);
+        for copy in Symmetry::all(false) {
+            let turned = copy.apply_state(&state);
+            let (turned_canonical, turned_symmetry) = canonicalise(&turned);
+            assert_eq!(turned_canonical, canonical);
+            assert_eq!(turned_symmetry.apply_state(&turned), canonical);
+
+            // an action picked for the representative maps back to the same
+            // move in the copy's frame
+            for action in ABSOLUTE.iter() {
+                let picked = turned_symmetry.apply_action(copy.apply_action(*action));
+                let expected = symmetry.apply_action(*action);
+                assert_eq!(picked, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn relative_states_are_only_mirrored() {
+        let mut state = lopsided();
+        state.relative = true;
+        let (_, symmetry) = canonicalise(&state);
+        assert!(Symmetry::all(true).contains(&symmetry));
+    }
+}
@@ -0,0 +1,131 @@
+use std::io::{stdout, Stdout, Write};
+use std::time::Duration;
+
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyModifiers};
+use crossterm::style::{Color, Print, ResetColor, SetForegroundColor};
+use crossterm::{cursor, execute, queue, terminal};
+
+use crate::render::Renderer;
+use crate::snake::{Action, Arena};
+
+// what a key press asks the game loop to do
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Input {
+    Action(Action),
+    Bound(usize),
+    Reload,
+    Quit,
+}
+
+// draws the arena with ANSI escapes so it can be watched over ssh. the
+// terminal is switched to raw mode on the alternate screen for as long as
+// the renderer is alive and restored when it is dropped
+pub struct TerminalRenderer {
+    out: Stdout,
+}
+
+impl TerminalRenderer {
+    pub fn new() -> crossterm::Result<TerminalRenderer> {
+        let mut out = stdout();
+        terminal::enable_raw_mode()?;
+        execute!(
+            out,
+            terminal::EnterAlternateScreen,
+            terminal::Clear(terminal::ClearType::All),
+            cursor::Hide
+        )?;
+        return Ok(TerminalRenderer { out: out });
+    }
+
+    // waits at most timeout for a key press and translates it
+    pub fn poll_input(&self, timeout: Duration) -> crossterm::Result<Option<Input>> {
+        if !event::poll(timeout)? {
+            return Ok(None);
+        }
+        if let Event::Key(key) = event::read()? {
+            return Ok(key_to_input(key));
+        }
+        return Ok(None);
+    }
+
+    fn draw(&mut self, arena: &Arena) -> crossterm::Result<()> {
+        let width = arena.arena_size.0;
+        let height = arena.arena_size.1;
+        let head = arena.snake[arena.snake.len() - 1];
+
+        queue!(self.out, cursor::MoveTo(0, 0), ResetColor)?;
+        queue!(
+            self.out,
+            Print(format!(
+                "bound {}  length {}  (arrows move, 1-4 bound, enter reload, q quit)",
+                arena.bound(),
+                arena.snake.len()
+            )),
+            terminal::Clear(terminal::ClearType::UntilNewLine)
+        )?;
+
+        let border = "#".repeat(width as usize * 2 + 2);
+        queue!(self.out, cursor::MoveTo(0, 1), Print(&border))?;
+        // y grows upwards like in the glium window, so the top row is the last one
+        for row in 0..height {
+            let y = height - 1 - row;
+            queue!(self.out, cursor::MoveTo(0, row as u16 + 2), Print("#"))?;
+            for x in 0..width {
+                if (x, y) == head {
+                    queue!(self.out, SetForegroundColor(Color::Green), Print("██"))?;
+                } else if arena.snake.contains(&(x, y)) {
+                    queue!(self.out, SetForegroundColor(Color::DarkGreen), Print("██"))?;
+                } else if arena.apple_pos.2 && (x, y) == (arena.apple_pos.0, arena.apple_pos.1) {
+                    queue!(self.out, SetForegroundColor(Color::Red), Print("██"))?;
+                } else {
+                    queue!(self.out, Print("  "))?;
+                }
+                queue!(self.out, ResetColor)?;
+            }
+            queue!(self.out, Print("#"))?;
+        }
+        queue!(
+            self.out,
+            cursor::MoveTo(0, height as u16 + 2),
+            Print(&border)
+        )?;
+        self.out.flush()?;
+        return Ok(());
+    }
+}
+
+impl Renderer for TerminalRenderer {
+    fn render(&mut self, arena: &Arena) {
+        self.draw(arena).unwrap();
+    }
+}
+
+impl Drop for TerminalRenderer {
+    fn drop(&mut self) {
+        let _ = execute!(
+            self.out,
+            ResetColor,
+            cursor::Show,
+            terminal::LeaveAlternateScreen
+        );
+        let _ = terminal::disable_raw_mode();
+    }
+}
+
+fn key_to_input(key: KeyEvent) -> Option<Input> {
+    match key.code {
+        KeyCode::Up => Some(Input::Action(Action::YPos)),
+        KeyCode::Down => Some(Input::Action(Action::YNeg)),
+        KeyCode::Left => Some(Input::Action(Action::XNeg)),
+        KeyCode::Right => Some(Input::Action(Action::XPos)),
+        KeyCode::Char('1') => Some(Input::Bound(3)),
+        KeyCode::Char('2') => Some(Input::Bound(5)),
+        KeyCode::Char('3') => Some(Input::Bound(7)),
+        KeyCode::Char('4') => Some(Input::Bound(9)),
+        KeyCode::Enter => Some(Input::Reload),
+        KeyCode::Char('q') | KeyCode::Esc => Some(Input::Quit),
+        // raw mode swallows the signal, so ctrl-c has to be handled by hand
+        KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => Some(Input::Quit),
+        _ => None,
+    }
+}
@@ -0,0 +1,342 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::packed::PackedState;
+use crate::sharded::ShardedTable;
+use crate::snake::{Action, Arena, TickOutcome};
+use crate::{QTable, VisitTable};
+
+// the update rurel's QLearning makes: a state/action pair seen for the first
+// time starts out at initial_value, after that it moves alpha of the way
+// towards reward + gamma * (best value of the state it led to)
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct QLearning {
+    pub alpha: f64,
+    pub gamma: f64,
+    pub initial_value: f64,
+}
+
+impl QLearning {
+    pub fn new(alpha: f64, gamma: f64, initial_value: f64) -> QLearning {
+        return QLearning {
+            alpha: alpha,
+            gamma: gamma,
+            initial_value: initial_value,
+        };
+    }
+
+    // max_next is the best value of the state the action led to, None if it
+    // was never seen
+    pub fn value(&self, max_next: Option<f64>, current_value: Option<f64>, reward: f64) -> f64 {
+        let max_next = max_next.unwrap_or(self.initial_value);
+        return current_value.map_or(self.initial_value, |x| {
+            (1.0 - self.alpha) * x + self.alpha * (reward + self.gamma * max_next)
+        });
+    }
+}
+
+// what a stretch of play went through
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct TrainingStats {
+    pub ticks: u64,
+    // states this trainer added to the table
+    pub new_states: usize,
+    // games that ended, and the final lengths of those games added together
+    pub episodes: u32,
+    pub total_length: usize,
+}
+
+impl TrainingStats {
+    pub fn add_episode(&mut self, final_length: usize) {
+        self.episodes += 1;
+        self.total_length += final_length;
+    }
+
+    pub fn merge(&mut self, other: TrainingStats) {
+        self.ticks += other.ticks;
+        self.new_states += other.new_states;
+        self.episodes += other.episodes;
+        self.total_length += other.total_length;
+    }
+
+    pub fn average_length(&self) -> f64 {
+        if self.episodes == 0 {
+            return 0.0;
+        }
+        return self.total_length as f64 / self.episodes as f64;
+    }
+}
+
+pub trait TerminationStrategy {
+    // called after every step with what it earned and whether the snake died
+    fn should_stop(&mut self, reward: f64, died: bool) -> bool;
+}
+
+// learns a q table by exploring an arena with random actions. the reward for
+// each step is read from the arena rather than from the state, so the keys
+// in the table are nothing but what the snake saw. trainers sharing a table
+// all learn into it at once
+pub struct Trainer {
+    q: Arc<ShardedTable>,
+}
+
+impl Trainer {
+    pub fn new() -> Trainer {
+        return Trainer::with_table(Arc::new(ShardedTable::new(1)));
+    }
+
+    pub fn with_table(q: Arc<ShardedTable>) -> Trainer {
+        return Trainer { q: q };
+    }
+
+    pub fn table(&self) -> &Arc<ShardedTable> {
+        return &self.q;
+    }
+
+    pub fn expected_values(&self, state: &PackedState) -> Option<HashMap<Action, f64>> {
+        return self.q.values(state);
+    }
+
+    pub fn expected_value(&self, state: &PackedState, action: &Action) -> Option<f64> {
+        return self.q.value(state, action);
+    }
+
+    // number of states with learned values
+    pub fn table_size(&self) -> usize {
+        return self.q.len();
+    }
+
+    pub fn export_learned_values(&self) -> QTable {
+        return self.q.snapshot().0;
+    }
+
+    // swaps the table for a private one holding values and visits. trainers
+    // that shared the old table keep it, and see none of this
+    pub fn import_tables(&mut self, values: QTable, visits: VisitTable) {
+        self.q = Arc::new(ShardedTable::from_tables(values, visits, self.q.shards()));
+    }
+
+    // how many times the value of action in state has been updated
+    pub fn visits(&self, state: &PackedState, action: &Action) -> u64 {
+        return self.q.visits(state, action);
+    }
+
+    pub fn export_visits(&self) -> VisitTable {
+        return self.q.snapshot().1;
+    }
+
+    // see ShardedTable::best_action
+    pub fn best_action(&self, state: &PackedState) -> Option<Action> {
+        return self.q.best_action(state);
+    }
+
+    pub fn train(
+        &mut self,
+        arena: &mut Arena,
+        learning: &QLearning,
+        termination: &mut dyn TerminationStrategy,
+    ) -> TrainingStats {
+        let mut stats = TrainingStats::default();
+        loop {
+            let state = arena.key;
+            let action = arena.random_action(&state.actions());
+            let outcome = arena.tick(arena.world_action(action));
+            stats.ticks += 1;
+            if let TickOutcome::Died { final_length, .. } = outcome {
+                stats.add_episode(final_length);
+            }
+            let reward = arena.reward_for_last_action;
+            // the value of dying is learned against the first state of the
+            // next game, as it was when the arena reset itself
+            if arena.finished() {
+                arena.reset();
+            }
+
+            let max_next = self.q.max_value(&arena.key);
+            if self.q.update(state, action, |current| {
+                learning.value(max_next, current, reward)
+            }) {
+                stats.new_states += 1;
+            }
+
+            if termination.should_stop(reward, outcome.died()) {
+                return stats;
+            }
+        }
+    }
+}
+
+pub struct FixedIterations {
+    curr_iteration: u32,
+    target_iterations: u32,
+}
+
+impl FixedIterations {
+    pub fn new(target_iterations: u32) -> FixedIterations {
+        return FixedIterations {
+            curr_iteration: 0,
+            target_iterations: target_iterations,
+        };
+    }
+}
+
+impl TerminationStrategy for FixedIterations {
+    fn should_stop(&mut self, _reward: f64, _died: bool) -> bool {
+        self.curr_iteration += 1;
+        return self.curr_iteration >= self.target_iterations;
+    }
+}
+
+pub struct TimePassed {
+    termination_time: std::time::Instant,
+}
+
+impl TimePassed {
+    pub fn new(time_to_train: std::time::Duration) -> TimePassed {
+        TimePassed {
+            termination_time: std::time::Instant::now() + time_to_train,
+        }
+    }
+}
+
+impl TerminationStrategy for TimePassed {
+    fn should_stop(&mut self, _reward: f64, _died: bool) -> bool {
+        if let Option::Some(_) =
+            std::time::Instant::now().checked_duration_since(self.termination_time)
+        {
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// stops as soon as flag is raised, otherwise whenever inner would
+pub struct StopFlag<T> {
+    inner: T,
+    flag: Arc<AtomicBool>,
+}
+
+impl<T: TerminationStrategy> StopFlag<T> {
+    pub fn new(inner: T, flag: Arc<AtomicBool>) -> StopFlag<T> {
+        return StopFlag {
+            inner: inner,
+            flag: flag,
+        };
+    }
+}
+
+impl<T: TerminationStrategy> TerminationStrategy for StopFlag<T> {
+    fn should_stop(&mut self, reward: f64, died: bool) -> bool {
+        return self.flag.load(Ordering::SeqCst) || self.inner.should_stop(reward, died);
+    }
+}
+
+pub struct NumGames {
+    curr_game: u32,
+    target_games: u32,
+}
+
+impl NumGames {
+    pub fn new(target_games: u32) -> NumGames {
+        return NumGames {
+            curr_game: 0,
+            target_games: target_games,
+        };
+    }
+}
+
+impl TerminationStrategy for NumGames {
+    fn should_stop(&mut self, _reward: f64, died: bool) -> bool {
+        if died {
+            self.curr_game += 1;
+        }
+        return self.curr_game == self.target_games;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::MyState;
+
+    const LEARNING: QLearning = QLearning {
+        alpha: 0.5,
+        gamma: 0.9,
+        initial_value: 2.0,
+    };
+
+    #[test]
+    fn first_visit_starts_at_the_initial_value() {
+        assert_eq!(LEARNING.value(Some(10.0), None, 5.0), 2.0);
+    }
+
+    #[test]
+    fn moves_alpha_of_the_way_to_the_target() {
+        // 0.5 * 1 + 0.5 * (1 + 0.9 * 10)
+        assert_eq!(LEARNING.value(Some(10.0), Some(1.0), 1.0), 5.5);
+        // an unseen next state is worth the initial value
+        assert_eq!(LEARNING.value(None, Some(1.0), 1.0), 1.9);
+    }
+
+    #[test]
+    fn best_action_reads_the_imported_table() {
+        let mut state = MyState::new(3);
+        state.curr_apple = (1, 0);
+        let state = PackedState::from(&state);
+        let mut values = HashMap::new();
+        values.insert(
+            state,
+            vec![(Action::XNeg, -1.0), (Action::YPos, 3.0)]
+                .into_iter()
+                .collect(),
+        );
+
+        let mut trainer = Trainer::new();
+        assert_eq!(trainer.best_action(&state), None);
+        trainer.import_tables(values, HashMap::new());
+        assert_eq!(trainer.best_action(&state), Some(Action::YPos));
+        assert_eq!(trainer.table_size(), 1);
+    }
+
+    #[test]
+    fn importing_leaves_shared_tables_alone() {
+        let shared = Arc::new(ShardedTable::new(4));
+        shared.update(PackedState::from(&MyState::new(3)), Action::XPos, |_| 1.0);
+        let mut trainer = Trainer::with_table(shared.clone());
+        trainer.import_tables(HashMap::new(), HashMap::new());
+        assert_eq!(trainer.table_size(), 0);
+        assert_eq!(shared.len(), 1);
+    }
+
+    #[test]
+    fn training_fills_the_table() {
+        let mut arena = Arena::new((8, 8), 3, Some(3));
+        let mut trainer = Trainer::new();
+        let stats = trainer.train(&mut arena, &LEARNING, &mut FixedIterations::new(500));
+        assert_eq!(stats.ticks, 500);
+        assert!(stats.episodes > 0);
+        assert_eq!(stats.new_states, trainer.table_size());
+
+        let visits: u64 = trainer
+            .export_visits()
+            .values()
+            .flat_map(|actions| actions.values())
+            .sum();
+        assert_eq!(visits, 500);
+    }
+
+    #[test]
+    fn training_is_reproducible() {
+        let run = || {
+            let mut arena = Arena::new((8, 8), 3, Some(11));
+            let mut trainer = Trainer::new();
+            trainer.train(&mut arena, &LEARNING, &mut NumGames::new(3));
+            return trainer.export_learned_values();
+        };
+        assert_eq!(run(), run());
+    }
+}